@@ -22,7 +22,7 @@ fn main() {
     let ts: Vec<_> = (1..100).collect();
     println!("0,{},{}", y0[0], y0[1]);
     for &t in &ts {
-        let (_tret, &[x, xdot]) = solver.step(t as _, StepKind::Normal).unwrap();
+        let (_tret, &[x, xdot], _outcome) = solver.step(t as _, StepKind::Normal).unwrap();
         println!("{},{},{}", t, x, xdot);
     }
 }
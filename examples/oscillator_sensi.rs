@@ -48,8 +48,12 @@ fn main() {
     let ts: Vec<_> = (1..100).collect();
     println!("0,{},{}", y0[0], y0[1]);
     for &t in &ts {
-        let (_tret, &[x, xdot], [&[dy0_dy00, dy1_dy00], &[dy0_dy01, dy1_dy01], &[dy0_dk, dy1_dk]]) =
-            solver.step(t as _, StepKind::Normal).unwrap();
+        let (
+            _tret,
+            &[x, xdot],
+            [&[dy0_dy00, dy1_dy00], &[dy0_dy01, dy1_dy01], &[dy0_dk, dy1_dk]],
+            _outcome,
+        ) = solver.step(t as _, StepKind::Normal).unwrap();
         println!(
             "{},{},{},{},{},{},{},{},{}",
             t, x, xdot, dy0_dy00, dy1_dy00, dy0_dy01, dy1_dy01, dy0_dk, dy1_dk
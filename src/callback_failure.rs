@@ -0,0 +1,53 @@
+use std::{
+    any::Any,
+    cell::Cell,
+    os::raw::c_int,
+    panic::{self, AssertUnwindSafe},
+};
+
+use crate::{BoxedError, Error, Realtype, Result, RhsResult};
+
+/// The failure of a callback which cannot be reported through cvode, that is
+/// a panic, which must not unwind across the C frames of cvode, or a
+/// [`RhsResult::Failed`]. It is stored until control is back in Rust.
+#[derive(Default)]
+pub(crate) struct CallbackFailure {
+    panic: Cell<Option<Box<dyn Any + Send>>>,
+    error: Cell<Option<(Realtype, BoxedError)>>,
+}
+
+impl CallbackFailure {
+    /// Calls `f`, a callback called by cvode at time `t`, and returns its
+    /// flag. If it panics or fails, the failure is stored and an
+    /// unrecoverable failure is returned to cvode.
+    pub(crate) fn catch(&self, t: Realtype, f: impl FnOnce() -> RhsResult) -> c_int {
+        match panic::catch_unwind(AssertUnwindSafe(f)) {
+            Ok(RhsResult::Failed(source)) => {
+                // Only the first failure is kept, the following ones being
+                // most likely consequences of it.
+                let first = self.error.take().unwrap_or((t, source));
+                self.error.set(Some(first));
+                -1
+            }
+            Ok(res) => res.to_c_int(),
+            Err(payload) => {
+                let first = self.panic.take().unwrap_or(payload);
+                self.panic.set(Some(first));
+                -1
+            }
+        }
+    }
+
+    /// Resumes the panic stored since the last call, if any, or replaces the
+    /// result of cvode by the stored [`Error::Rhs`].
+    pub(crate) fn finish<T>(&self, res: Result<T>) -> Result<T> {
+        if let Some(payload) = self.panic.take() {
+            self.error.take();
+            panic::resume_unwind(payload)
+        }
+        match self.error.take() {
+            Some((t, source)) => Err(Error::Rhs { t, source }),
+            None => res,
+        }
+    }
+}
@@ -0,0 +1,485 @@
+//! Optional callbacks shared between [`SolverNoSensi`](crate::SolverNoSensi)
+//! and [`SolverSensi`](crate::SolverSensi), and their `extern "C"` wrappers.
+
+use std::os::raw::c_int;
+
+#[cfg(feature = "klu")]
+use crate::SparseMatrixView;
+use crate::{
+    callback_failure::CallbackFailure, check_flag_is_succes, BandMatrixView,
+    CvodeMemoryBlockNonNullPtr, DenseMatrixView, Error, LinearSolver, NVectorSerial,
+    PreconditionerSide, Realtype, Result, RhsResult, RootDirection,
+};
+
+pub(crate) type DenseJacobianFn<UserData, const N: usize> = Box<
+    dyn FnMut(
+        Realtype,
+        &[Realtype; N],
+        &[Realtype; N],
+        &mut DenseMatrixView<N>,
+        &UserData,
+    ) -> RhsResult,
+>;
+
+pub(crate) type BandJacobianFn<UserData, const N: usize> = Box<
+    dyn FnMut(
+        Realtype,
+        &[Realtype; N],
+        &[Realtype; N],
+        &mut BandMatrixView<N>,
+        &UserData,
+    ) -> RhsResult,
+>;
+
+#[cfg(feature = "klu")]
+pub(crate) type SparseJacobianFn<UserData, const N: usize> = Box<
+    dyn FnMut(
+        Realtype,
+        &[Realtype; N],
+        &[Realtype; N],
+        &mut SparseMatrixView<N>,
+        &UserData,
+    ) -> RhsResult,
+>;
+
+pub(crate) type JacTimesSetupFn<UserData, const N: usize> =
+    Box<dyn FnMut(Realtype, &[Realtype; N], &[Realtype; N], &UserData) -> RhsResult>;
+
+pub(crate) type JacTimesVecFn<UserData, const N: usize> = Box<
+    dyn FnMut(
+        Realtype,
+        &[Realtype; N],
+        &[Realtype; N],
+        &[Realtype; N],
+        &mut [Realtype; N],
+        &UserData,
+    ) -> RhsResult,
+>;
+
+pub(crate) type RhsFn<UserData, const N: usize> =
+    Box<dyn FnMut(Realtype, &[Realtype; N], &mut [Realtype; N], &UserData) -> RhsResult>;
+
+pub(crate) type PrecSetupFn<UserData, const N: usize> = Box<
+    dyn FnMut(
+        Realtype,
+        &[Realtype; N],
+        &[Realtype; N],
+        bool,
+        Realtype,
+        &UserData,
+    ) -> (bool, RhsResult),
+>;
+
+pub(crate) type PrecSolveFn<UserData, const N: usize> = Box<
+    dyn FnMut(
+        Realtype,
+        &[Realtype; N],
+        &[Realtype; N],
+        &[Realtype; N],
+        &mut [Realtype; N],
+        Realtype,
+        Realtype,
+        PreconditionerSide,
+        &UserData,
+    ) -> RhsResult,
+>;
+
+/// The root function, which fills a slice whose length is the number of
+/// roots it was registered with.
+pub(crate) type RootFn<UserData, const N: usize> =
+    Box<dyn FnMut(Realtype, &[Realtype; N], &mut [Realtype], &UserData) -> RhsResult>;
+
+/// A user-supplied Jacobian function.
+pub(crate) enum Jacobian<UserData, const N: usize> {
+    Dense(DenseJacobianFn<UserData, N>),
+    Band(BandJacobianFn<UserData, N>),
+    #[cfg(feature = "klu")]
+    Sparse(SparseJacobianFn<UserData, N>),
+}
+
+impl<UserData, const N: usize> Jacobian<UserData, N> {
+    /// Stores the Jacobian function in `data` and registers it with cvode.
+    ///
+    /// Fails if the matrix used by `linear_solver` does not match the
+    /// kind of Jacobian function.
+    pub(crate) fn register<D>(
+        self,
+        mem: CvodeMemoryBlockNonNullPtr,
+        linear_solver: LinearSolver,
+        data: &mut D,
+    ) -> Result<()>
+    where
+        D: CallbackData<UserData, N>,
+    {
+        let jac_fn: sundials_sys::CVLsJacFn = match (&self, linear_solver) {
+            (Jacobian::Dense(_), LinearSolver::Dense) => {
+                let fn_ptr =
+                    wrap_dense_jac::<UserData, D, N> as extern "C" fn(_, _, _, _, _, _, _, _) -> _;
+                Some(unsafe { std::mem::transmute(fn_ptr) })
+            }
+            (Jacobian::Band(_), LinearSolver::Band { .. }) => {
+                let fn_ptr =
+                    wrap_band_jac::<UserData, D, N> as extern "C" fn(_, _, _, _, _, _, _, _) -> _;
+                Some(unsafe { std::mem::transmute(fn_ptr) })
+            }
+            #[cfg(feature = "klu")]
+            (Jacobian::Sparse(_), LinearSolver::Sparse { .. }) => {
+                let fn_ptr =
+                    wrap_sparse_jac::<UserData, D, N> as extern "C" fn(_, _, _, _, _, _, _, _) -> _;
+                Some(unsafe { std::mem::transmute(fn_ptr) })
+            }
+            _ => {
+                return Err(Error::IllegalInput {
+                    func_id: "CVodeSetJacFn",
+                    reason: "the Jacobian function does not match the matrix of the linear solver",
+                })
+            }
+        };
+        data.callbacks_mut().jacobian = Some(self);
+        let flag = unsafe { sundials_sys::CVodeSetJacFn(mem.as_raw(), jac_fn) };
+        check_flag_is_succes(flag, "CVodeSetJacFn")
+    }
+}
+
+/// The optional callbacks of a solver.
+pub(crate) struct Callbacks<UserData, const N: usize> {
+    pub(crate) jacobian: Option<Jacobian<UserData, N>>,
+    pub(crate) jac_times_setup: Option<JacTimesSetupFn<UserData, N>>,
+    pub(crate) jac_times_vec: Option<JacTimesVecFn<UserData, N>>,
+    pub(crate) jac_times_rhs: Option<RhsFn<UserData, N>>,
+    pub(crate) prec_setup: Option<PrecSetupFn<UserData, N>>,
+    pub(crate) prec_solve: Option<PrecSolveFn<UserData, N>>,
+    pub(crate) root: Option<RootFn<UserData, N>>,
+    pub(crate) num_roots: usize,
+}
+
+impl<UserData, const N: usize> Callbacks<UserData, N> {
+    pub(crate) fn new() -> Self {
+        Callbacks {
+            jacobian: None,
+            jac_times_setup: None,
+            jac_times_vec: None,
+            jac_times_rhs: None,
+            prec_setup: None,
+            prec_solve: None,
+            root: None,
+            num_roots: 0,
+        }
+    }
+}
+
+/// Registers with cvode the Jacobian-times-vector setup and product functions
+/// stored in `data`. Missing ones are replaced by cvode defaults.
+pub(crate) fn register_jac_times<UserData, D, const N: usize>(
+    mem: CvodeMemoryBlockNonNullPtr,
+    data: &D,
+) -> Result<()>
+where
+    D: CallbackData<UserData, N>,
+{
+    let callbacks = data.callbacks();
+    let jtsetup: sundials_sys::CVLsJacTimesSetupFn = callbacks.jac_times_setup.as_ref().map(|_| {
+        let fn_ptr = wrap_jac_times_setup::<UserData, D, N> as extern "C" fn(_, _, _, _) -> _;
+        unsafe { std::mem::transmute(fn_ptr) }
+    });
+    let jtimes: sundials_sys::CVLsJacTimesVecFn = callbacks.jac_times_vec.as_ref().map(|_| {
+        let fn_ptr =
+            wrap_jac_times_vec::<UserData, D, N> as extern "C" fn(_, _, _, _, _, _, _) -> _;
+        unsafe { std::mem::transmute(fn_ptr) }
+    });
+    let flag = unsafe { sundials_sys::CVodeSetJacTimes(mem.as_raw(), jtsetup, jtimes) };
+    check_flag_is_succes(flag, "CVodeSetJacTimes")
+}
+
+/// Registers with cvode the right-hand side used for the difference quotient
+/// approximation of Jacobian-times-vector products stored in `data`.
+pub(crate) fn register_jac_times_rhs<UserData, D, const N: usize>(
+    mem: CvodeMemoryBlockNonNullPtr,
+    data: &D,
+) -> Result<()>
+where
+    D: CallbackData<UserData, N>,
+{
+    let jtimes_rhs: sundials_sys::CVRhsFn = data.callbacks().jac_times_rhs.as_ref().map(|_| {
+        let fn_ptr = wrap_jac_times_rhs::<UserData, D, N> as extern "C" fn(_, _, _, _) -> _;
+        unsafe { std::mem::transmute(fn_ptr) }
+    });
+    let flag = unsafe { sundials_sys::CVodeSetJacTimesRhsFn(mem.as_raw(), jtimes_rhs) };
+    check_flag_is_succes(flag, "CVodeSetJacTimesRhsFn")
+}
+
+/// Registers with cvode the preconditioner setup and solve functions stored
+/// in `data`.
+pub(crate) fn register_preconditioner<UserData, D, const N: usize>(
+    mem: CvodeMemoryBlockNonNullPtr,
+    data: &D,
+) -> Result<()>
+where
+    D: CallbackData<UserData, N>,
+{
+    let callbacks = data.callbacks();
+    let psetup: sundials_sys::CVLsPrecSetupFn = callbacks.prec_setup.as_ref().map(|_| {
+        let fn_ptr = wrap_prec_setup::<UserData, D, N> as extern "C" fn(_, _, _, _, _, _, _) -> _;
+        unsafe { std::mem::transmute(fn_ptr) }
+    });
+    let psolve: sundials_sys::CVLsPrecSolveFn = callbacks.prec_solve.as_ref().map(|_| {
+        let fn_ptr =
+            wrap_prec_solve::<UserData, D, N> as extern "C" fn(_, _, _, _, _, _, _, _, _) -> _;
+        unsafe { std::mem::transmute(fn_ptr) }
+    });
+    let flag = unsafe { sundials_sys::CVodeSetPreconditioner(mem.as_raw(), psetup, psolve) };
+    check_flag_is_succes(flag, "CVodeSetPreconditioner")
+}
+
+/// Registers with cvode the root function `root` with `num_roots` components,
+/// and the directions of the crossings to detect for each of them. `root` is
+/// stored in `data` once cvode has accepted it.
+pub(crate) fn register_root<UserData, D, const N: usize>(
+    mem: CvodeMemoryBlockNonNullPtr,
+    data: &mut D,
+    root: RootFn<UserData, N>,
+    num_roots: usize,
+    directions: &[RootDirection],
+) -> Result<()>
+where
+    D: CallbackData<UserData, N>,
+{
+    if directions.len() != num_roots {
+        return Err(Error::IllegalInput {
+            func_id: "CVodeSetRootDirection",
+            reason: "there must be one direction per component of the root function",
+        });
+    }
+    let fn_ptr = wrap_root::<UserData, D, N> as extern "C" fn(_, _, _, _) -> _;
+    let g: sundials_sys::CVRootFn = Some(unsafe { std::mem::transmute(fn_ptr) });
+    let flag = unsafe { sundials_sys::CVodeRootInit(mem.as_raw(), num_roots as c_int, g) };
+    check_flag_is_succes(flag, "CVodeRootInit")?;
+    let callbacks = data.callbacks_mut();
+    callbacks.root = Some(root);
+    callbacks.num_roots = num_roots;
+    if !directions.is_empty() {
+        let mut directions: Vec<c_int> = directions.iter().map(|&d| d as c_int).collect();
+        let flag =
+            unsafe { sundials_sys::CVodeSetRootDirection(mem.as_raw(), directions.as_mut_ptr()) };
+        check_flag_is_succes(flag, "CVodeSetRootDirection")?;
+    }
+    Ok(())
+}
+
+/// Implemented by the pinned user data of the solvers, so that the wrappers
+/// below can be shared.
+pub(crate) trait CallbackData<UserData, const N: usize> {
+    fn callbacks(&self) -> &Callbacks<UserData, N>;
+
+    fn callbacks_mut(&mut self) -> &mut Callbacks<UserData, N>;
+
+    /// Borrows the callbacks, to call them, along with the user data and the
+    /// failure they are called with.
+    fn split(&mut self) -> (&mut Callbacks<UserData, N>, &UserData, &CallbackFailure);
+}
+
+#[allow(clippy::too_many_arguments)]
+extern "C" fn wrap_dense_jac<UserData, D, const N: usize>(
+    t: Realtype,
+    y: *const NVectorSerial<N>,
+    fy: *const NVectorSerial<N>,
+    jac: *mut DenseMatrixView<N>,
+    data: *mut D,
+    _tmp1: *const NVectorSerial<N>,
+    _tmp2: *const NVectorSerial<N>,
+    _tmp3: *const NVectorSerial<N>,
+) -> c_int
+where
+    D: CallbackData<UserData, N>,
+{
+    let y = unsafe { &*y }.as_slice();
+    let fy = unsafe { &*fy }.as_slice();
+    let jac_matrix = unsafe { &mut *jac };
+    let (callbacks, user_data, failure) = unsafe { &mut *data }.split();
+    failure.catch(t, || match &mut callbacks.jacobian {
+        Some(Jacobian::Dense(jac)) => jac(t, y, fy, jac_matrix, user_data),
+        _ => RhsResult::NonRecoverableError(1),
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+extern "C" fn wrap_band_jac<UserData, D, const N: usize>(
+    t: Realtype,
+    y: *const NVectorSerial<N>,
+    fy: *const NVectorSerial<N>,
+    jac: *mut BandMatrixView<N>,
+    data: *mut D,
+    _tmp1: *const NVectorSerial<N>,
+    _tmp2: *const NVectorSerial<N>,
+    _tmp3: *const NVectorSerial<N>,
+) -> c_int
+where
+    D: CallbackData<UserData, N>,
+{
+    let y = unsafe { &*y }.as_slice();
+    let fy = unsafe { &*fy }.as_slice();
+    let jac_matrix = unsafe { &mut *jac };
+    let (callbacks, user_data, failure) = unsafe { &mut *data }.split();
+    failure.catch(t, || match &mut callbacks.jacobian {
+        Some(Jacobian::Band(jac)) => jac(t, y, fy, jac_matrix, user_data),
+        _ => RhsResult::NonRecoverableError(1),
+    })
+}
+
+#[cfg(feature = "klu")]
+#[allow(clippy::too_many_arguments)]
+extern "C" fn wrap_sparse_jac<UserData, D, const N: usize>(
+    t: Realtype,
+    y: *const NVectorSerial<N>,
+    fy: *const NVectorSerial<N>,
+    jac: *mut SparseMatrixView<N>,
+    data: *mut D,
+    _tmp1: *const NVectorSerial<N>,
+    _tmp2: *const NVectorSerial<N>,
+    _tmp3: *const NVectorSerial<N>,
+) -> c_int
+where
+    D: CallbackData<UserData, N>,
+{
+    let y = unsafe { &*y }.as_slice();
+    let fy = unsafe { &*fy }.as_slice();
+    let jac_matrix = unsafe { &mut *jac };
+    let (callbacks, user_data, failure) = unsafe { &mut *data }.split();
+    failure.catch(t, || match &mut callbacks.jacobian {
+        Some(Jacobian::Sparse(jac)) => jac(t, y, fy, jac_matrix, user_data),
+        _ => RhsResult::NonRecoverableError(1),
+    })
+}
+
+extern "C" fn wrap_jac_times_setup<UserData, D, const N: usize>(
+    t: Realtype,
+    y: *const NVectorSerial<N>,
+    fy: *const NVectorSerial<N>,
+    data: *mut D,
+) -> c_int
+where
+    D: CallbackData<UserData, N>,
+{
+    let y = unsafe { &*y }.as_slice();
+    let fy = unsafe { &*fy }.as_slice();
+    let (callbacks, user_data, failure) = unsafe { &mut *data }.split();
+    failure.catch(t, || match &mut callbacks.jac_times_setup {
+        Some(jtsetup) => jtsetup(t, y, fy, user_data),
+        None => RhsResult::NonRecoverableError(1),
+    })
+}
+
+extern "C" fn wrap_jac_times_vec<UserData, D, const N: usize>(
+    v: *const NVectorSerial<N>,
+    jv: *mut NVectorSerial<N>,
+    t: Realtype,
+    y: *const NVectorSerial<N>,
+    fy: *const NVectorSerial<N>,
+    data: *mut D,
+    _tmp: *const NVectorSerial<N>,
+) -> c_int
+where
+    D: CallbackData<UserData, N>,
+{
+    let v = unsafe { &*v }.as_slice();
+    let jv = unsafe { &mut *jv }.as_slice_mut();
+    let y = unsafe { &*y }.as_slice();
+    let fy = unsafe { &*fy }.as_slice();
+    let (callbacks, user_data, failure) = unsafe { &mut *data }.split();
+    failure.catch(t, || match &mut callbacks.jac_times_vec {
+        Some(jtimes) => jtimes(t, y, fy, v, jv, user_data),
+        None => RhsResult::NonRecoverableError(1),
+    })
+}
+
+extern "C" fn wrap_jac_times_rhs<UserData, D, const N: usize>(
+    t: Realtype,
+    y: *const NVectorSerial<N>,
+    ydot: *mut NVectorSerial<N>,
+    data: *mut D,
+) -> c_int
+where
+    D: CallbackData<UserData, N>,
+{
+    let y = unsafe { &*y }.as_slice();
+    let ydot = unsafe { &mut *ydot }.as_slice_mut();
+    let (callbacks, user_data, failure) = unsafe { &mut *data }.split();
+    failure.catch(t, || match &mut callbacks.jac_times_rhs {
+        Some(f) => f(t, y, ydot, user_data),
+        None => RhsResult::NonRecoverableError(1),
+    })
+}
+
+extern "C" fn wrap_prec_setup<UserData, D, const N: usize>(
+    t: Realtype,
+    y: *const NVectorSerial<N>,
+    fy: *const NVectorSerial<N>,
+    jok: sundials_sys::booleantype,
+    jcur: *mut sundials_sys::booleantype,
+    gamma: Realtype,
+    data: *mut D,
+) -> c_int
+where
+    D: CallbackData<UserData, N>,
+{
+    let y = unsafe { &*y }.as_slice();
+    let fy = unsafe { &*fy }.as_slice();
+    let (callbacks, user_data, failure) = unsafe { &mut *data }.split();
+    failure.catch(t, || match &mut callbacks.prec_setup {
+        Some(psetup) => {
+            let (jacobian_updated, res) = psetup(t, y, fy, jok != 0, gamma, user_data);
+            unsafe { *jcur = jacobian_updated as _ };
+            res
+        }
+        None => RhsResult::NonRecoverableError(1),
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+extern "C" fn wrap_prec_solve<UserData, D, const N: usize>(
+    t: Realtype,
+    y: *const NVectorSerial<N>,
+    fy: *const NVectorSerial<N>,
+    r: *const NVectorSerial<N>,
+    z: *mut NVectorSerial<N>,
+    gamma: Realtype,
+    delta: Realtype,
+    lr: c_int,
+    data: *mut D,
+) -> c_int
+where
+    D: CallbackData<UserData, N>,
+{
+    let y = unsafe { &*y }.as_slice();
+    let fy = unsafe { &*fy }.as_slice();
+    let r = unsafe { &*r }.as_slice();
+    let z = unsafe { &mut *z }.as_slice_mut();
+    let side = if lr == PreconditionerSide::Left as c_int {
+        PreconditionerSide::Left
+    } else {
+        PreconditionerSide::Right
+    };
+    let (callbacks, user_data, failure) = unsafe { &mut *data }.split();
+    failure.catch(t, || match &mut callbacks.prec_solve {
+        Some(psolve) => psolve(t, y, fy, r, z, gamma, delta, side, user_data),
+        None => RhsResult::NonRecoverableError(1),
+    })
+}
+
+extern "C" fn wrap_root<UserData, D, const N: usize>(
+    t: Realtype,
+    y: *const NVectorSerial<N>,
+    gout: *mut Realtype,
+    data: *mut D,
+) -> c_int
+where
+    D: CallbackData<UserData, N>,
+{
+    let y = unsafe { &*y }.as_slice();
+    let (callbacks, user_data, failure) = unsafe { &mut *data }.split();
+    let gout = unsafe { std::slice::from_raw_parts_mut(gout, callbacks.num_roots) };
+    failure.catch(t, || match &mut callbacks.root {
+        Some(g) => g(t, y, gout, user_data),
+        None => RhsResult::NonRecoverableError(1),
+    })
+}
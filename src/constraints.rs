@@ -0,0 +1,25 @@
+use crate::Realtype;
+
+/// An inequality constraint on a component of the state.
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Constraint {
+    /// The component is free.
+    None = 0,
+    /// The component must stay `>= 0`.
+    NonNegative = 1,
+    /// The component must stay `> 0`.
+    Positive = 2,
+    /// The component must stay `<= 0`.
+    NonPositive = -1,
+    /// The component must stay `< 0`.
+    Negative = -2,
+}
+
+impl Constraint {
+    /// Returns the value encoding the constraint in the constraints vector
+    /// of cvode.
+    pub(crate) fn value(self) -> Realtype {
+        self as i32 as Realtype
+    }
+}
@@ -1,18 +1,74 @@
-//! Wrapper around cvode, without sensitivities
+//! Wrapper around cvode, without sensitivities, and the methods shared with
+//! the solver with sensitivities
 
 use std::{convert::TryInto, os::raw::c_int, pin::Pin};
 
-use sundials_sys::{SUNLinearSolver, SUNMatrix};
-
+#[cfg(feature = "klu")]
+use crate::SparseMatrixView;
 use crate::{
-    check_flag_is_succes, check_non_null, AbsTolerance, CvodeMemoryBlock,
-    CvodeMemoryBlockNonNullPtr, LinearMultistepMethod, NVectorSerial, NVectorSerialHeapAllocated,
-    Realtype, Result, RhsResult, StepKind,
+    callback_failure::CallbackFailure,
+    callbacks::{
+        register_jac_times, register_jac_times_rhs, register_preconditioner, register_root,
+        CallbackData, Callbacks, Jacobian, RootFn,
+    },
+    solver_core::{forward_to_core, SolverCore},
+    AbsTolerance, BandMatrixView, BuiltinPreconditioner, Constraint, DenseMatrixView, Indextype,
+    LinearMultistepMethod, LinearSolver, NVectorSerial, NVectorSerialHeapAllocated,
+    NonlinearSolver, PreconditionerSide, Realtype, Result, RhsResult, RootDirection, StepKind,
+    StepOutcome,
 };
 
-struct WrappingUserData<UserData, F> {
-    actual_user_data: UserData,
-    f: F,
+pub(crate) struct WrappingUserData<UserData, F, S, const N: usize> {
+    pub(crate) actual_user_data: UserData,
+    pub(crate) f: F,
+    pub(crate) sensi: S,
+    pub(crate) callbacks: Callbacks<UserData, N>,
+    pub(crate) failure: CallbackFailure,
+}
+
+impl<UserData, F, S, const N: usize> CallbackData<UserData, N>
+    for WrappingUserData<UserData, F, S, N>
+{
+    fn callbacks(&self) -> &Callbacks<UserData, N> {
+        &self.callbacks
+    }
+
+    fn callbacks_mut(&mut self) -> &mut Callbacks<UserData, N> {
+        &mut self.callbacks
+    }
+
+    fn split(&mut self) -> (&mut Callbacks<UserData, N>, &UserData, &CallbackFailure) {
+        (&mut self.callbacks, &self.actual_user_data, &self.failure)
+    }
+}
+
+/// The ODE solver, computing the sensitivities described by `S`.
+///
+/// It is used through its aliases [`SolverNoSensi`](crate::SolverNoSensi)
+/// and [`SolverSensi`](crate::SolverSensi), which provide the constructors
+/// and the steps. The other methods are shared by both.
+///
+/// The projection onto an invariant manifold is not available: its
+/// `CVodeSetProjFn` is only provided by cvode, and not by cvodes 5.7 on which
+/// all the solvers are built.
+///
+/// # Type Arguments
+///
+/// - `F` is the type of the right-hand side function
+///
+/// - `UserData` is the type of the supplementary arguments for the
+///   right-hand-side. If unused, should be `()`.
+///
+/// - `S` is `()` without sensitivities, and
+///   [`Sensitivities`](crate::Sensitivities) with sensitivities.
+///
+/// - `N` is the "problem size", that is the dimension of the state space.
+pub struct GenericSolver<UserData, F, S, const N: usize> {
+    pub(crate) core: SolverCore,
+    pub(crate) y0: NVectorSerialHeapAllocated<N>,
+    atol: AbsTolerance<N>,
+    pub(crate) user_data: Pin<Box<WrappingUserData<UserData, F, S, N>>>,
+    constraints: Option<NVectorSerialHeapAllocated<N>>,
 }
 
 /// The ODE solver without sensitivities.
@@ -25,161 +81,425 @@ struct WrappingUserData<UserData, F> {
 /// right-hand-side. If unused, should be `()`.
 ///
 /// - `N` is the "problem size", that is the dimension of the state space.
-pub struct Solver<UserData, F, const N: usize> {
-    mem: CvodeMemoryBlockNonNullPtr,
-    y0: NVectorSerialHeapAllocated<N>,
-    sunmatrix: SUNMatrix,
-    linsolver: SUNLinearSolver,
-    atol: AbsTolerance<N>,
-    user_data: Pin<Box<WrappingUserData<UserData, F>>>,
-}
+pub type Solver<UserData, F, const N: usize> = GenericSolver<UserData, F, (), N>;
 
-extern "C" fn wrap_f<UserData, F, const N: usize>(
+pub(crate) extern "C" fn wrap_f<UserData, F, S, const N: usize>(
     t: Realtype,
     y: *const NVectorSerial<N>,
     ydot: *mut NVectorSerial<N>,
-    data: *const WrappingUserData<UserData, F>,
+    data: *mut WrappingUserData<UserData, F, S, N>,
 ) -> c_int
 where
-    F: Fn(Realtype, &[Realtype; N], &mut [Realtype; N], &UserData) -> RhsResult,
+    F: FnMut(Realtype, &[Realtype; N], &mut [Realtype; N], &UserData) -> RhsResult,
 {
     let y = unsafe { &*y }.as_slice();
     let ydot = unsafe { &mut *ydot }.as_slice_mut();
     let WrappingUserData {
         actual_user_data: data,
         f,
-    } = unsafe { &*data };
-    let res = f(t, y, ydot, data);
-    match res {
-        RhsResult::Ok => 0,
-        RhsResult::RecoverableError(e) => e as c_int,
-        RhsResult::NonRecoverableError(e) => -(e as c_int),
-    }
+        failure,
+        ..
+    } = unsafe { &mut *data };
+    failure.catch(t, || f(t, y, ydot, data))
 }
 
-impl<UserData, F, const N: usize> Solver<UserData, F, N>
+extern "C" fn wrap_local_f<UserData, F, S, const N: usize>(
+    _n_local: Indextype,
+    t: Realtype,
+    y: *const NVectorSerial<N>,
+    g: *mut NVectorSerial<N>,
+    data: *mut WrappingUserData<UserData, F, S, N>,
+) -> c_int
 where
-    F: Fn(Realtype, &[Realtype; N], &mut [Realtype; N], &UserData) -> RhsResult,
+    F: FnMut(Realtype, &[Realtype; N], &mut [Realtype; N], &UserData) -> RhsResult,
 {
-    /// Create a new solver.
-    pub fn new(
+    wrap_f::<UserData, F, S, N>(t, y, g, data)
+}
+
+impl<UserData, F, S, const N: usize> GenericSolver<UserData, F, S, N>
+where
+    F: FnMut(Realtype, &[Realtype; N], &mut [Realtype; N], &UserData) -> RhsResult,
+{
+    /// Creates a solver and initializes cvode, leaving the initialization of
+    /// the sensitivities to the caller.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn create(
         method: LinearMultistepMethod,
+        nonlinear_solver: NonlinearSolver,
         f: F,
         t0: Realtype,
         y0: &[Realtype; N],
         rtol: Realtype,
         atol: AbsTolerance<N>,
         user_data: UserData,
+        sensi: S,
     ) -> Result<Self> {
-        assert_eq!(y0.len(), N);
-        let mem: CvodeMemoryBlockNonNullPtr = {
-            let mem_maybenull = unsafe { sundials_sys::CVodeCreate(method as c_int) };
-            check_non_null(mem_maybenull as *mut CvodeMemoryBlock, "CVodeCreate")?.into()
-        };
         let y0 = NVectorSerialHeapAllocated::new_from(y0);
-        let matrix = {
-            let matrix = unsafe {
-                sundials_sys::SUNDenseMatrix(N.try_into().unwrap(), N.try_into().unwrap())
-            };
-            check_non_null(matrix, "SUNDenseMatrix")?
-        };
-        let linsolver = {
-            let linsolver = unsafe { sundials_sys::SUNLinSol_Dense(y0.as_raw(), matrix.as_ptr()) };
-            check_non_null(linsolver, "SUNDenseLinearSolver")?
-        };
+        let core = SolverCore::new(method, nonlinear_solver, unsafe { y0.as_raw() }, N)?;
         let user_data = Box::pin(WrappingUserData {
             actual_user_data: user_data,
             f,
+            sensi,
+            callbacks: Callbacks::new(),
+            failure: CallbackFailure::default(),
         });
-        let res = Solver {
-            mem,
+        let mut res = GenericSolver {
+            core,
             y0,
-            sunmatrix: matrix.as_ptr(),
-            linsolver: linsolver.as_ptr(),
             atol,
             user_data,
+            constraints: None,
         };
         {
-            let fn_ptr = wrap_f::<UserData, F, N> as extern "C" fn(_, _, _, _) -> _;
-            let flag = unsafe {
-                sundials_sys::CVodeInit(
-                    mem.as_raw(),
-                    Some(std::mem::transmute(fn_ptr)),
-                    t0,
-                    res.y0.as_raw(),
-                )
-            };
-            check_flag_is_succes(flag, "CVodeInit")?;
+            let fn_ptr = wrap_f::<UserData, F, S, N> as extern "C" fn(_, _, _, _) -> _;
+            res.core.init(
+                Some(unsafe { std::mem::transmute(fn_ptr) }),
+                t0,
+                unsafe { res.y0.as_raw() },
+                unsafe { res.user_data.as_mut().get_unchecked_mut() } as *mut _ as _,
+            )?;
         }
-        match &res.atol {
-            &AbsTolerance::Scalar(atol) => {
-                let flag = unsafe { sundials_sys::CVodeSStolerances(mem.as_raw(), rtol, atol) };
-                check_flag_is_succes(flag, "CVodeSStolerances")?;
-            }
-            AbsTolerance::Vector(atol) => {
-                let flag =
-                    unsafe { sundials_sys::CVodeSVtolerances(mem.as_raw(), rtol, atol.as_raw()) };
-                check_flag_is_succes(flag, "CVodeSVtolerances")?;
-            }
-        }
-        {
-            let flag = unsafe {
-                sundials_sys::CVodeSetLinearSolver(
-                    mem.as_raw(),
-                    linsolver.as_ptr(),
-                    matrix.as_ptr(),
-                )
-            };
-            check_flag_is_succes(flag, "CVodeSetLinearSolver")?;
+        res.core.capture(|| res.atol.set(res.core.mem, rtol))?;
+        Ok(res)
+    }
+
+    /// Takes a step of the state, leaving its sensitivities to the caller.
+    pub(crate) fn step_state(
+        &mut self,
+        tout: Realtype,
+        step_kind: StepKind,
+    ) -> Result<(Realtype, StepOutcome)> {
+        let res = self.core.step(
+            tout,
+            unsafe { self.y0.as_raw() },
+            step_kind,
+            self.user_data.callbacks.num_roots,
+        );
+        self.user_data.failure.finish(res)
+    }
+
+    forward_to_core!();
+
+    /// Sets the function computing the Jacobian of the right-hand side, when
+    /// using [`LinearSolver::Dense`](crate::LinearSolver::Dense).
+    ///
+    /// The function takes as arguments `(t, y, f(t, y), jac, user_data)`,
+    /// and must fill `jac` with the Jacobian `df/dy(t, y)`. `jac` is zeroed
+    /// before each call.
+    ///
+    /// If no Jacobian function is set, cvode approximates the Jacobian with
+    /// difference quotients.
+    pub fn set_dense_jacobian<J>(&mut self, jac: J) -> Result<()>
+    where
+        J: FnMut(
+                Realtype,
+                &[Realtype; N],
+                &[Realtype; N],
+                &mut DenseMatrixView<N>,
+                &UserData,
+            ) -> RhsResult
+            + 'static,
+    {
+        Jacobian::Dense(Box::new(jac)).register(
+            self.core.mem,
+            self.core.linear_solver("CVodeSetJacFn")?,
+            unsafe { self.user_data.as_mut().get_unchecked_mut() },
+        )
+    }
+
+    /// Sets the function computing the Jacobian of the right-hand side, when
+    /// using [`LinearSolver::Band`](crate::LinearSolver::Band).
+    ///
+    /// See [`Self::set_dense_jacobian`], only the elements in the band
+    /// of `jac` can be accessed.
+    pub fn set_band_jacobian<J>(&mut self, jac: J) -> Result<()>
+    where
+        J: FnMut(
+                Realtype,
+                &[Realtype; N],
+                &[Realtype; N],
+                &mut BandMatrixView<N>,
+                &UserData,
+            ) -> RhsResult
+            + 'static,
+    {
+        Jacobian::Band(Box::new(jac)).register(
+            self.core.mem,
+            self.core.linear_solver("CVodeSetJacFn")?,
+            unsafe { self.user_data.as_mut().get_unchecked_mut() },
+        )
+    }
+
+    /// Sets the function computing the Jacobian of the right-hand side, when
+    /// using [`LinearSolver::Sparse`](crate::LinearSolver::Sparse).
+    ///
+    /// See [`Self::set_dense_jacobian`], the sparsity pattern must be set
+    /// by the function at each call.
+    #[cfg(feature = "klu")]
+    pub fn set_sparse_jacobian<J>(&mut self, jac: J) -> Result<()>
+    where
+        J: FnMut(
+                Realtype,
+                &[Realtype; N],
+                &[Realtype; N],
+                &mut SparseMatrixView<N>,
+                &UserData,
+            ) -> RhsResult
+            + 'static,
+    {
+        Jacobian::Sparse(Box::new(jac)).register(
+            self.core.mem,
+            self.core.linear_solver("CVodeSetJacFn")?,
+            unsafe { self.user_data.as_mut().get_unchecked_mut() },
+        )
+    }
+
+    /// Sets the function computing Jacobian-times-vector products, when using
+    /// [`LinearSolver::Krylov`](crate::LinearSolver::Krylov).
+    ///
+    /// The function takes as arguments `(t, y, f(t, y), v, jv, user_data)`,
+    /// and must fill `jv` with the product `df/dy(t, y) * v`.
+    ///
+    /// If no such function is set, cvode approximates the product with
+    /// difference quotients.
+    pub fn set_jac_times<JV>(&mut self, jtimes: JV) -> Result<()>
+    where
+        JV: FnMut(
+                Realtype,
+                &[Realtype; N],
+                &[Realtype; N],
+                &[Realtype; N],
+                &mut [Realtype; N],
+                &UserData,
+            ) -> RhsResult
+            + 'static,
+    {
+        let data = unsafe { self.user_data.as_mut().get_unchecked_mut() };
+        data.callbacks.jac_times_vec = Some(Box::new(jtimes));
+        register_jac_times(self.core.mem, data)
+    }
+
+    /// Sets the function preprocessing the Jacobian data needed by the
+    /// function set with [`Self::set_jac_times`].
+    ///
+    /// The function takes as arguments `(t, y, f(t, y), user_data)`,
+    /// and is called each time the Jacobian data may need to be updated.
+    pub fn set_jac_times_setup<JS>(&mut self, jtsetup: JS) -> Result<()>
+    where
+        JS: FnMut(Realtype, &[Realtype; N], &[Realtype; N], &UserData) -> RhsResult + 'static,
+    {
+        let data = unsafe { self.user_data.as_mut().get_unchecked_mut() };
+        data.callbacks.jac_times_setup = Some(Box::new(jtsetup));
+        register_jac_times(self.core.mem, data)
+    }
+
+    /// Sets the right-hand side used by the difference quotient approximation
+    /// of Jacobian-times-vector products, instead of the one given at
+    /// construction.
+    ///
+    /// It is useful when the right-hand side can be approximated by a cheaper
+    /// function.
+    pub fn set_jac_times_rhs<FJ>(&mut self, f: FJ) -> Result<()>
+    where
+        FJ: FnMut(Realtype, &[Realtype; N], &mut [Realtype; N], &UserData) -> RhsResult + 'static,
+    {
+        let data = unsafe { self.user_data.as_mut().get_unchecked_mut() };
+        data.callbacks.jac_times_rhs = Some(Box::new(f));
+        register_jac_times_rhs(self.core.mem, data)
+    }
+
+    /// Sets the preconditioner used by a [`LinearSolver::Krylov`](crate::LinearSolver::Krylov), applied on
+    /// `side`.
+    ///
+    /// `psetup` takes as arguments `(t, y, f(t, y), jok, gamma, user_data)`,
+    /// and must prepare the preconditioner for an approximation of the matrix
+    /// `I - gamma * df/dy`. `jok` indicates that saved Jacobian data can be
+    /// reused, and `psetup` must return whether the Jacobian data was updated.
+    ///
+    /// `psolve` takes as arguments `(t, y, f(t, y), r, z, gamma, delta, lr, user_data)`,
+    /// and must solve the preconditioner system `P z = r` where `P` is the left or
+    /// right preconditioner as indicated by `lr`. If an iterative method
+    /// is used to solve it, the residual must be smaller than `delta`.
+    pub fn set_preconditioner<PS, PV>(
+        &mut self,
+        side: PreconditionerSide,
+        psetup: PS,
+        psolve: PV,
+    ) -> Result<()>
+    where
+        PS: FnMut(
+                Realtype,
+                &[Realtype; N],
+                &[Realtype; N],
+                bool,
+                Realtype,
+                &UserData,
+            ) -> (bool, RhsResult)
+            + 'static,
+        PV: FnMut(
+                Realtype,
+                &[Realtype; N],
+                &[Realtype; N],
+                &[Realtype; N],
+                &mut [Realtype; N],
+                Realtype,
+                Realtype,
+                PreconditionerSide,
+                &UserData,
+            ) -> RhsResult
+            + 'static,
+    {
+        self.core.set_preconditioner_side(side)?;
+        let data = unsafe { self.user_data.as_mut().get_unchecked_mut() };
+        data.callbacks.prec_setup = Some(Box::new(psetup));
+        data.callbacks.prec_solve = Some(Box::new(psolve));
+        register_preconditioner(self.core.mem, data)
+    }
+
+    /// Sets a preconditioner provided by cvode, to be used by a
+    /// [`LinearSolver::Krylov`](crate::LinearSolver::Krylov) and applied on `side`.
+    pub fn set_builtin_preconditioner(
+        &mut self,
+        side: PreconditionerSide,
+        preconditioner: BuiltinPreconditioner,
+    ) -> Result<()> {
+        let fn_ptr = wrap_local_f::<UserData, F, S, N> as extern "C" fn(_, _, _, _, _) -> _;
+        self.core.set_builtin_preconditioner(
+            side,
+            preconditioner,
+            N,
+            Some(unsafe { std::mem::transmute(fn_ptr) }),
+        )
+    }
+
+    /// Sets the root function, whose roots are located during integration.
+    ///
+    /// The function takes as arguments `(t, y, gout, user_data)`, and
+    /// must fill `gout` with the `NR` components of the root function at
+    /// `(t, y)`. The crossings of zero of the `i`-th component are detected in
+    /// `directions[i]`, and make the steps stop with
+    /// [`StepOutcome::RootFound`].
+    pub fn set_root_fn<G, const NR: usize>(
+        &mut self,
+        directions: [RootDirection; NR],
+        mut g: G,
+    ) -> Result<()>
+    where
+        G: FnMut(Realtype, &[Realtype; N], &mut [Realtype; NR], &UserData) -> RhsResult + 'static,
+    {
+        let data = unsafe { self.user_data.as_mut().get_unchecked_mut() };
+        let root: RootFn<UserData, N> =
+            Box::new(move |t, y, gout, user_data| g(t, y, gout.try_into().unwrap(), user_data));
+        register_root(self.core.mem, data, root, NR, &directions)
+    }
+
+    /// Sets an inequality constraint on each component of the state. When a
+    /// step violates them, cvode reduces the step size and retries it.
+    ///
+    /// Setting all the components to [`Constraint::None`] removes the
+    /// constraints.
+    pub fn set_constraints(&mut self, constraints: &[Constraint; N]) -> Result<()> {
+        if constraints.iter().all(|&c| c == Constraint::None) {
+            self.core.set_constraints(std::ptr::null_mut())?;
+            self.constraints = None;
+            return Ok(());
         }
-        {
-            let flag = unsafe {
-                sundials_sys::CVodeSetUserData(
-                    mem.as_raw(),
-                    std::mem::transmute(res.user_data.as_ref().get_ref()),
-                )
-            };
-            check_flag_is_succes(flag, "CVodeSetUserData")?;
+        let mut vector = NVectorSerialHeapAllocated::<N>::new();
+        for (v, c) in vector.as_slice_mut().iter_mut().zip(constraints) {
+            *v = c.value();
         }
-        Ok(res)
+        self.core.set_constraints(unsafe { vector.as_raw() })?;
+        self.constraints = Some(vector);
+        Ok(())
+    }
+
+    /// Returns the `k`-th derivative of the interpolated state at time `t`.
+    ///
+    /// `t` must be within the last internal step taken by the solver, and `k`
+    /// must not exceed the current order of the method.
+    pub fn dky(&self, t: Realtype, k: usize) -> Result<[Realtype; N]> {
+        let dky = NVectorSerialHeapAllocated::<N>::new();
+        self.core.dky(t, k, unsafe { dky.as_raw() })?;
+        Ok(*dky.as_slice())
+    }
+}
+
+impl<UserData, F, const N: usize> GenericSolver<UserData, F, (), N>
+where
+    F: FnMut(Realtype, &[Realtype; N], &mut [Realtype; N], &UserData) -> RhsResult,
+{
+    /// Create a new solver.
+    ///
+    /// The nonlinear iteration is the Newton iteration, with
+    /// [`LinearSolver::Dense`] as linear solver.
+    pub fn new(
+        method: LinearMultistepMethod,
+        f: F,
+        t0: Realtype,
+        y0: &[Realtype; N],
+        rtol: Realtype,
+        atol: AbsTolerance<N>,
+        user_data: UserData,
+    ) -> Result<Self> {
+        Self::with_solver(
+            method,
+            LinearSolver::Dense,
+            f,
+            t0,
+            y0,
+            rtol,
+            atol,
+            user_data,
+        )
+    }
+
+    /// Create a new solver.
+    ///
+    /// `solver` is the nonlinear iteration: a [`LinearSolver`] for the Newton
+    /// iteration, or a [`NonlinearSolver`].
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_solver(
+        method: LinearMultistepMethod,
+        solver: impl Into<NonlinearSolver>,
+        f: F,
+        t0: Realtype,
+        y0: &[Realtype; N],
+        rtol: Realtype,
+        atol: AbsTolerance<N>,
+        user_data: UserData,
+    ) -> Result<Self> {
+        Self::create(method, solver.into(), f, t0, y0, rtol, atol, user_data, ())
+    }
+
+    /// Reinitializes the solver with a new initial state, keeping all the
+    /// allocations, options and callbacks of the solver.
+    pub fn reinit(&mut self, t0: Realtype, y0: &[Realtype; N]) -> Result<()> {
+        *self.y0.as_slice_mut() = *y0;
+        self.core.reinit(t0, unsafe { self.y0.as_raw() })
     }
 
     /// Takes a step according to `step_kind` (see [`StepKind`]).
     ///
-    /// Returns a tuple `(t_out,&y(t_out))` where `t_out` is the time
-    /// reached by the solver as dictated by `step_kind`, and `y(t_out)` is an
-    /// array of the state variables at that time.
+    /// Returns a tuple `(t_out,&y(t_out),outcome)` where `t_out` is the time
+    /// reached by the solver as dictated by `step_kind` or a root, `y(t_out)`
+    /// is an array of the state variables at that time, and `outcome` tells
+    /// how the step ended.
+    ///
+    /// The failures of the functions called by cvode are reported as
+    /// described in the [crate documentation](crate#functions-called-by-cvode).
     pub fn step(
         &mut self,
         tout: Realtype,
         step_kind: StepKind,
-    ) -> Result<(Realtype, &[Realtype; N])> {
-        let mut tret = 0.;
-        let flag = unsafe {
-            sundials_sys::CVode(
-                self.mem.as_raw(),
-                tout,
-                self.y0.as_raw(),
-                &mut tret,
-                step_kind as c_int,
-            )
-        };
-        check_flag_is_succes(flag, "CVode")?;
-        Ok((tret, self.y0.as_slice()))
-    }
-}
-
-impl<UserData, F, const N: usize> Drop for Solver<UserData, F, N> {
-    fn drop(&mut self) {
-        unsafe { sundials_sys::CVodeFree(&mut self.mem.as_raw()) }
-        unsafe { sundials_sys::SUNLinSolFree(self.linsolver) };
-        unsafe { sundials_sys::SUNMatDestroy(self.sunmatrix) };
+    ) -> Result<(Realtype, &[Realtype; N], StepOutcome)> {
+        let (tret, outcome) = self.step_state(tout, step_kind)?;
+        Ok((tret, self.y0.as_slice(), outcome))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::RhsResult;
+    use crate::{GramSchmidt, KrylovMethod, Limits, LinearSolver, RhsResult, RootCrossing};
 
     use super::*;
 
@@ -207,4 +527,584 @@ mod tests {
         )
         .unwrap();
     }
+
+    fn jac(
+        _t: super::Realtype,
+        _y: &[Realtype; 2],
+        _fy: &[Realtype; 2],
+        jac: &mut DenseMatrixView<2>,
+        _data: &(),
+    ) -> RhsResult {
+        jac[(0, 1)] = 1.;
+        jac[(1, 0)] = -1.;
+        RhsResult::Ok
+    }
+
+    #[test]
+    fn dense_jacobian() {
+        let y0 = [0., 1.];
+        let mut solver = Solver::new(
+            LinearMultistepMethod::Bdf,
+            f,
+            0.,
+            &y0,
+            1e-6,
+            AbsTolerance::scalar(1e-6),
+            (),
+        )
+        .unwrap();
+        solver.set_dense_jacobian(jac).unwrap();
+        let (_tret, y, outcome) = solver.step(1., StepKind::Normal).unwrap();
+        assert!((y[0] - 1f64.sin()).abs() < 1e-4);
+        assert_eq!(outcome, StepOutcome::Reached);
+        let stats = solver.stats().unwrap();
+        assert!(stats.num_steps > 0);
+        assert!(stats.linear_solver.unwrap().num_jac_evals > 0);
+    }
+
+    #[test]
+    fn band_jacobian() {
+        let y0 = [0., 1.];
+        let mut solver = Solver::with_solver(
+            LinearMultistepMethod::Bdf,
+            LinearSolver::Band {
+                upper_bandwidth: 1,
+                lower_bandwidth: 1,
+            },
+            f,
+            0.,
+            &y0,
+            1e-6,
+            AbsTolerance::scalar(1e-6),
+            (),
+        )
+        .unwrap();
+        assert!(solver.set_dense_jacobian(jac).is_err());
+        solver
+            .set_band_jacobian(|_t, _y, _fy, jac: &mut BandMatrixView<2>, _data| {
+                jac[(0, 1)] = 1.;
+                jac[(1, 0)] = -1.;
+                RhsResult::Ok
+            })
+            .unwrap();
+        let (_tret, y, _outcome) = solver.step(1., StepKind::Normal).unwrap();
+        assert!((y[0] - 1f64.sin()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn krylov() {
+        let y0 = [0., 1.];
+        let mut solver = Solver::with_solver(
+            LinearMultistepMethod::Bdf,
+            LinearSolver::Krylov {
+                method: KrylovMethod::Spgmr {
+                    gram_schmidt: GramSchmidt::Modified,
+                },
+                max_krylov_dim: 0,
+                eps_lin: Some(0.01),
+            },
+            f,
+            0.,
+            &y0,
+            1e-6,
+            AbsTolerance::scalar(1e-6),
+            (),
+        )
+        .unwrap();
+        assert!(solver.set_dense_jacobian(jac).is_err());
+        let (_tret, y, _outcome) = solver.step(1., StepKind::Normal).unwrap();
+        assert!((y[0] - 1f64.sin()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn jac_times() {
+        let y0 = [0., 1.];
+        let mut solver = Solver::with_solver(
+            LinearMultistepMethod::Bdf,
+            LinearSolver::Krylov {
+                method: KrylovMethod::Spbcgs,
+                max_krylov_dim: 0,
+                eps_lin: None,
+            },
+            f,
+            0.,
+            &y0,
+            1e-6,
+            AbsTolerance::scalar(1e-6),
+            (),
+        )
+        .unwrap();
+        solver
+            .set_jac_times(
+                |_t, _y, _fy, v: &[Realtype; 2], jv: &mut [Realtype; 2], _data| {
+                    *jv = [v[1], -v[0]];
+                    RhsResult::Ok
+                },
+            )
+            .unwrap();
+        let (_tret, y, _outcome) = solver.step(1., StepKind::Normal).unwrap();
+        assert!((y[0] - 1f64.sin()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn preconditioner() {
+        let y0 = [0., 1.];
+        let mut solver = Solver::with_solver(
+            LinearMultistepMethod::Bdf,
+            LinearSolver::Krylov {
+                method: KrylovMethod::Sptfqmr,
+                max_krylov_dim: 0,
+                eps_lin: None,
+            },
+            f,
+            0.,
+            &y0,
+            1e-6,
+            AbsTolerance::scalar(1e-6),
+            (),
+        )
+        .unwrap();
+        solver
+            .set_preconditioner(
+                PreconditionerSide::Left,
+                |_t, _y, _fy, _jok, _gamma, _data| (false, RhsResult::Ok),
+                |_t,
+                 _y,
+                 _fy,
+                 r: &[Realtype; 2],
+                 z: &mut [Realtype; 2],
+                 _gamma,
+                 _delta,
+                 _lr,
+                 _data| {
+                    *z = *r;
+                    RhsResult::Ok
+                },
+            )
+            .unwrap();
+        let (_tret, y, _outcome) = solver.step(1., StepKind::Normal).unwrap();
+        assert!((y[0] - 1f64.sin()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn builtin_preconditioner() {
+        let y0 = [0., 1.];
+        let mut solver = Solver::with_solver(
+            LinearMultistepMethod::Bdf,
+            LinearSolver::Krylov {
+                method: KrylovMethod::Spgmr {
+                    gram_schmidt: GramSchmidt::Classical,
+                },
+                max_krylov_dim: 0,
+                eps_lin: None,
+            },
+            f,
+            0.,
+            &y0,
+            1e-6,
+            AbsTolerance::scalar(1e-6),
+            (),
+        )
+        .unwrap();
+        assert!(solver.builtin_preconditioner_stats().is_err());
+        solver
+            .set_builtin_preconditioner(
+                PreconditionerSide::Left,
+                BuiltinPreconditioner::Band {
+                    upper_bandwidth: 1,
+                    lower_bandwidth: 1,
+                },
+            )
+            .unwrap();
+        let (_tret, y, _outcome) = solver.step(1., StepKind::Normal).unwrap();
+        assert!((y[0] - 1f64.sin()).abs() < 1e-4);
+        assert!(solver.builtin_preconditioner_stats().unwrap().num_rhs_evals > 0);
+    }
+
+    #[test]
+    fn diagonal() {
+        let y0 = [0., 1.];
+        let mut solver = Solver::with_solver(
+            LinearMultistepMethod::Bdf,
+            LinearSolver::Diagonal,
+            f,
+            0.,
+            &y0,
+            1e-6,
+            AbsTolerance::scalar(1e-6),
+            (),
+        )
+        .unwrap();
+        assert!(solver.set_dense_jacobian(jac).is_err());
+        assert!(solver.builtin_preconditioner_stats().is_err());
+        let (_tret, y, _outcome) = solver.step(1., StepKind::Normal).unwrap();
+        assert!((y[0] - 1f64.sin()).abs() < 1e-4);
+        assert!(solver.stats().unwrap().linear_solver.is_none());
+        let stats = solver.diag_stats().unwrap();
+        assert!(stats.num_rhs_evals > 0);
+        assert_eq!(stats.last_flag, 0);
+    }
+
+    #[test]
+    fn fixed_point() {
+        let y0 = [0., 1.];
+        let mut solver = Solver::with_solver(
+            LinearMultistepMethod::Adams,
+            NonlinearSolver::FixedPoint { anderson_depth: 3 },
+            f,
+            0.,
+            &y0,
+            1e-6,
+            AbsTolerance::scalar(1e-6),
+            (),
+        )
+        .unwrap();
+        assert!(solver.set_dense_jacobian(jac).is_err());
+        assert!(solver.diag_stats().is_err());
+        let (_tret, y, _outcome) = solver.step(1., StepKind::Normal).unwrap();
+        assert!((y[0] - 1f64.sin()).abs() < 1e-4);
+        assert!(Solver::with_solver(
+            LinearMultistepMethod::Adams,
+            NonlinearSolver::FixedPoint {
+                anderson_depth: usize::MAX
+            },
+            f,
+            0.,
+            &y0,
+            1e-6,
+            AbsTolerance::scalar(1e-6),
+            (),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn root() {
+        let y0 = [0., 1.];
+        let mut solver = Solver::new(
+            LinearMultistepMethod::Adams,
+            f,
+            0.,
+            &y0,
+            1e-6,
+            AbsTolerance::scalar(1e-6),
+            (),
+        )
+        .unwrap();
+        solver
+            .set_root_fn(
+                [RootDirection::Increasing, RootDirection::Decreasing],
+                |_t, y, gout: &mut [Realtype; 2], _data| {
+                    *gout = [y[0] - 0.5, y[1]];
+                    RhsResult::Ok
+                },
+            )
+            .unwrap();
+        let (tret, _y, outcome) = solver.step(10., StepKind::Normal).unwrap();
+        assert!((tret - std::f64::consts::FRAC_PI_6).abs() < 1e-4);
+        assert_eq!(
+            outcome,
+            StepOutcome::RootFound(vec![Some(RootCrossing::Increasing), None])
+        );
+        let (tret, _y, outcome) = solver.step(10., StepKind::Normal).unwrap();
+        assert!((tret - std::f64::consts::FRAC_PI_2).abs() < 1e-4);
+        assert_eq!(
+            outcome,
+            StepOutcome::RootFound(vec![None, Some(RootCrossing::Decreasing)])
+        );
+    }
+
+    #[test]
+    fn stop_time() {
+        let y0 = [0., 1.];
+        let mut solver = Solver::new(
+            LinearMultistepMethod::Adams,
+            f,
+            0.,
+            &y0,
+            1e-6,
+            AbsTolerance::scalar(1e-6),
+            (),
+        )
+        .unwrap();
+        solver.set_stop_time(0.5).unwrap();
+        let (tret, y, outcome) = solver.step(1., StepKind::NormalTstop).unwrap();
+        assert_eq!(tret, 0.5);
+        assert!((y[0] - 0.5f64.sin()).abs() < 1e-4);
+        assert_eq!(outcome, StepOutcome::StopTimeReached);
+        let (tret, _y, outcome) = solver.step(1., StepKind::Normal).unwrap();
+        assert_eq!(tret, 0.5);
+        assert_eq!(outcome, StepOutcome::StopTimeReached);
+        solver.clear_stop_time();
+        let (tret, _y, outcome) = solver.step(1., StepKind::Normal).unwrap();
+        assert_eq!(tret, 1.);
+        assert_eq!(outcome, StepOutcome::Reached);
+        assert!(solver.step(2., StepKind::OneStepTstop).is_err());
+        solver.set_stop_time(1.5).unwrap();
+        let (tret, _y, outcome) = solver.step(2., StepKind::Normal).unwrap();
+        assert_eq!(tret, 1.5);
+        assert_eq!(outcome, StepOutcome::StopTimeReached);
+        // Cleared before being reached.
+        solver.set_stop_time(2.5).unwrap();
+        solver.clear_stop_time();
+        let (tret, _y, outcome) = solver.step(3., StepKind::Normal).unwrap();
+        assert_eq!(tret, 3.);
+        assert_eq!(outcome, StepOutcome::Reached);
+    }
+
+    #[test]
+    fn stop_time_reinit() {
+        let y0 = [0., 1.];
+        let mut solver = Solver::new(
+            LinearMultistepMethod::Adams,
+            f,
+            0.,
+            &y0,
+            1e-6,
+            AbsTolerance::scalar(1e-6),
+            (),
+        )
+        .unwrap();
+        solver.set_stop_time(0.5).unwrap();
+        solver.reinit(1., &y0).unwrap();
+        assert!(solver.step(2., StepKind::Normal).is_err());
+        solver.clear_stop_time();
+        let (tret, _y, outcome) = solver.step(2., StepKind::Normal).unwrap();
+        assert_eq!(tret, 2.);
+        assert_eq!(outcome, StepOutcome::Reached);
+        // Honoured again after a reinitialization before it.
+        assert!(solver.set_stop_time(0.5).is_err());
+        solver.reinit(0., &y0).unwrap();
+        solver.set_stop_time(0.5).unwrap();
+        let (tret, _y, outcome) = solver.step(1., StepKind::Normal).unwrap();
+        assert_eq!(tret, 0.5);
+        assert_eq!(outcome, StepOutcome::StopTimeReached);
+    }
+
+    #[test]
+    fn limits() {
+        let y0 = [0., 1.];
+        let mut solver = Solver::new(
+            LinearMultistepMethod::Adams,
+            f,
+            0.,
+            &y0,
+            1e-6,
+            AbsTolerance::scalar(1e-6),
+            (),
+        )
+        .unwrap();
+        assert!(solver
+            .set_limits(&Limits::new().min_step(0.1).max_step(0.01))
+            .is_err());
+        assert!(solver
+            .set_limits(&Limits::new().init_step(Realtype::NAN))
+            .is_err());
+        // Nothing is set when a limit is invalid.
+        assert!(solver
+            .set_limits(&Limits::new().max_num_steps(1).max_order(usize::MAX))
+            .is_err());
+        solver.step(0.1, StepKind::Normal).unwrap();
+        solver
+            .set_limits(&Limits::new().max_num_steps(2).max_order(5))
+            .unwrap();
+        match solver.step(10., StepKind::Normal) {
+            Err(
+                e @ crate::Error::StepFailed {
+                    failure: crate::CvodeFailure::TooMuchWork,
+                    message: Some(_),
+                    ..
+                },
+            ) => assert!(e.to_string().contains("CV_TOO_MUCH_WORK")),
+            _ => panic!("the step should fail with a message"),
+        }
+        solver
+            .set_limits(&Limits::new().max_num_steps(10_000).max_step(0.01))
+            .unwrap();
+        let (_tret, y, _outcome) = solver.step(10., StepKind::Normal).unwrap();
+        assert!((y[0] - 10f64.sin()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn dky() {
+        let y0 = [0., 1.];
+        let mut solver = Solver::new(
+            LinearMultistepMethod::Adams,
+            f,
+            0.,
+            &y0,
+            1e-8,
+            AbsTolerance::scalar(1e-8),
+            (),
+        )
+        .unwrap();
+        let (tret, y, _outcome) = solver.step(1., StepKind::OneStep).unwrap();
+        let y = *y;
+        assert_eq!(solver.dky(tret, 0).unwrap(), y);
+        let dy = solver.dky(tret, 1).unwrap();
+        assert!((dy[0] - tret.cos()).abs() < 1e-4);
+        assert!((dy[1] + tret.sin()).abs() < 1e-4);
+        assert!(solver.dky(tret + 1., 0).is_err());
+        assert!(solver.dky(tret, 20).is_err());
+    }
+
+    #[test]
+    fn reinit() {
+        let y0 = [0., 1.];
+        let mut solver = Solver::new(
+            LinearMultistepMethod::Bdf,
+            f,
+            0.,
+            &y0,
+            1e-6,
+            AbsTolerance::scalar(1e-6),
+            (),
+        )
+        .unwrap();
+        solver.set_dense_jacobian(jac).unwrap();
+        solver.step(1., StepKind::Normal).unwrap();
+        solver.reinit(1., &[1., 0.]).unwrap();
+        let (_tret, y, _outcome) = solver.step(2., StepKind::Normal).unwrap();
+        assert!((y[0] - 1f64.cos()).abs() < 1e-4);
+        assert!(solver.stats().unwrap().linear_solver.unwrap().num_jac_evals > 0);
+    }
+
+    #[test]
+    fn rhs_error() {
+        let y0 = [0., 1.];
+        let mut solver = Solver::new(
+            LinearMultistepMethod::Adams,
+            |t, y: &[Realtype; 2], ydot: &mut [Realtype; 2], _data: &()| {
+                if t > 0.5 {
+                    return RhsResult::Failed("the rate went NaN".into());
+                }
+                *ydot = [y[1], -y[0]];
+                RhsResult::Ok
+            },
+            0.,
+            &y0,
+            1e-6,
+            AbsTolerance::scalar(1e-6),
+            (),
+        )
+        .unwrap();
+        match solver.step(1., StepKind::Normal) {
+            Err(crate::Error::Rhs { t, source }) => {
+                assert!(t > 0.5);
+                assert_eq!(source.to_string(), "the rate went NaN");
+            }
+            _ => panic!("the step should fail with the error of the right-hand side"),
+        }
+    }
+
+    #[test]
+    fn panic() {
+        let y0 = [0., 1.];
+        let mut solver = Solver::new(
+            LinearMultistepMethod::Adams,
+            |_t, _y: &[Realtype; 2], _ydot: &mut [Realtype; 2], _data: &()| -> RhsResult {
+                panic!("rhs")
+            },
+            0.,
+            &y0,
+            1e-6,
+            AbsTolerance::scalar(1e-6),
+            (),
+        )
+        .unwrap();
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _ = solver.step(1., StepKind::Normal);
+        }));
+        assert_eq!(*res.unwrap_err().downcast::<&str>().unwrap(), "rhs");
+    }
+
+    #[test]
+    fn user_data() {
+        let y0 = [1.];
+        let mut num_calls = 0;
+        let mut solver = Solver::with_solver(
+            LinearMultistepMethod::Adams,
+            NonlinearSolver::FixedPoint { anderson_depth: 0 },
+            |_t, y: &[Realtype; 1], ydot: &mut [Realtype; 1], k: &Realtype| {
+                num_calls += 1;
+                *ydot = [-k * y[0]];
+                RhsResult::Ok
+            },
+            0.,
+            &y0,
+            1e-6,
+            AbsTolerance::scalar(1e-8),
+            1.,
+        )
+        .unwrap();
+        let (_tret, &[y1], _outcome) = solver.step(1., StepKind::Normal).unwrap();
+        assert!((y1 - (-1f64).exp()).abs() < 1e-4);
+        *solver.user_data_mut() = 0.;
+        assert_eq!(*solver.user_data(), 0.);
+        let (_tret, &[y2], _outcome) = solver.step(2., StepKind::Normal).unwrap();
+        assert!((y2 - y1).abs() < 1e-4);
+        let num_evals = solver.stats().unwrap().num_rhs_evals;
+        drop(solver);
+        assert_eq!(num_calls, num_evals);
+    }
+
+    #[test]
+    fn constraints() {
+        let y0 = [1., 0.];
+        let mut solver = Solver::new(
+            LinearMultistepMethod::Bdf,
+            |_t, y: &[Realtype; 2], ydot: &mut [Realtype; 2], _data: &()| {
+                *ydot = [-100. * y[0], 100. * y[0]];
+                RhsResult::Ok
+            },
+            0.,
+            &y0,
+            1e-4,
+            AbsTolerance::scalar(1e-8),
+            (),
+        )
+        .unwrap();
+        solver
+            .set_constraints(&[Constraint::NonNegative, Constraint::None])
+            .unwrap();
+        for &t in &[0.1, 1., 10.] {
+            let (_tret, &[a, b], _outcome) = solver.step(t, StepKind::Normal).unwrap();
+            assert!(a >= 0.);
+            assert!((a + b - 1.).abs() < 1e-4);
+        }
+        solver.set_constraints(&[Constraint::None; 2]).unwrap();
+    }
+
+    #[cfg(feature = "klu")]
+    #[test]
+    fn sparse_jacobian() {
+        use crate::SparseFormat;
+
+        let y0 = [0., 1.];
+        let mut solver = Solver::with_solver(
+            LinearMultistepMethod::Bdf,
+            LinearSolver::Sparse {
+                nnz: 2,
+                format: SparseFormat::Csc,
+            },
+            f,
+            0.,
+            &y0,
+            1e-6,
+            AbsTolerance::scalar(1e-6),
+            (),
+        )
+        .unwrap();
+        solver
+            .set_sparse_jacobian(|_t, _y, _fy, jac: &mut SparseMatrixView<2>, _data| {
+                let (index_pointers, index_values, data) = jac.parts_mut();
+                index_pointers.copy_from_slice(&[0, 1, 2]);
+                index_values.copy_from_slice(&[1, 0]);
+                data.copy_from_slice(&[-1., 1.]);
+                RhsResult::Ok
+            })
+            .unwrap();
+        let (_tret, y, _outcome) = solver.step(1., StepKind::Normal).unwrap();
+        assert!((y[0] - 1f64.sin()).abs() < 1e-4);
+    }
 }
@@ -0,0 +1,1025 @@
+//! Wrapper around cvodes, for adjoint sensitivity analysis
+
+use std::{
+    convert::TryInto,
+    os::raw::c_int,
+    pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use sundials_sys::SUNMatrix;
+
+use crate::{
+    callback_failure::CallbackFailure,
+    callbacks::{Callbacks, Jacobian},
+    cvode::{wrap_f, WrappingUserData},
+    solver_core::SolverCore,
+    AbsTolerance, BandMatrixView, DenseMatrixView, Error, IntegratorStats, Limits,
+    LinearMultistepMethod, LinearSolver, NVectorSerial, NVectorSerialDyn,
+    NVectorSerialHeapAllocated, NonlinearSolver, Realtype, Result, RhsResult, StepKind,
+    StepOutcome,
+};
+
+/// The interpolation of the forward solution between its checkpoints, used
+/// by the backward problems.
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdjointInterpolation {
+    /// Cubic Hermite interpolation.
+    Hermite = sundials_sys::CV_HERMITE,
+    /// Variable-degree polynomial interpolation.
+    Polynomial = sundials_sys::CV_POLYNOMIAL,
+}
+
+/// A backward problem of size `NB` of a [`SolverAdjoint`](crate::SolverAdjoint),
+/// returned by [`Solver::add_backward`].
+///
+/// It can only be used with the solver it was added to, the other ones
+/// returning an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackwardProblem<const NB: usize> {
+    solver_id: usize,
+    which: c_int,
+}
+
+/// The identifier of the next adjoint solver, telling the solver of a
+/// [`BackwardProblem`].
+static NEXT_SOLVER_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// The right-hand side of a backward problem or of its quadrature, whose
+/// sizes are only known by the wrappers built in [`Solver::add_backward`] and
+/// [`Solver::set_backward_quadrature`].
+type BackwardRhsFn<UserData, const N: usize> =
+    Box<dyn FnMut(Realtype, &[Realtype; N], &[Realtype], &mut [Realtype], &UserData) -> RhsResult>;
+
+/// The Jacobian function of a backward problem, the matrix being converted
+/// to the view matching its linear solver by the wrapper built in the
+/// `set_backward_*_jacobian` methods.
+type BackwardJacobianFn<UserData, const N: usize> = Box<
+    dyn FnMut(
+        Realtype,
+        &[Realtype; N],
+        &[Realtype],
+        &[Realtype],
+        SUNMatrix,
+        &UserData,
+    ) -> RhsResult,
+>;
+
+/// The user data of a backward problem. The user data and the failure of the
+/// forward problem are pointed to in its pinned user data, through
+/// [`Solver::forward_data`].
+struct BackwardUserData<UserData, const N: usize> {
+    actual_user_data: *const UserData,
+    failure: *const CallbackFailure,
+    f_b: BackwardRhsFn<UserData, N>,
+    jacobian: Option<BackwardJacobianFn<UserData, N>>,
+    f_qb: Option<BackwardRhsFn<UserData, N>>,
+    /// The size of the quadrature, `0` without quadrature.
+    quadrature_size: usize,
+}
+
+/// The ODE solver for adjoint sensitivity analysis.
+///
+/// The forward problem is integrated with [`Solver::step`], which stores
+/// checkpoints of its solution. Backward problems, added with
+/// [`Solver::add_backward`], are then integrated from the final time with
+/// [`Solver::step_backward`], their right-hand sides being given the forward
+/// solution interpolated between the checkpoints. A quadrature can be
+/// integrated along with each backward problem, added with
+/// [`Solver::set_backward_quadrature`].
+///
+/// The forward problem does not compute sensitivities, so the backward
+/// problems and quadratures depending on them (`CVodeInitBS` and
+/// `CVodeQuadInitBS`) are not available.
+///
+/// # Type Arguments
+///
+/// - `F` is the type of the right-hand side function
+///
+/// - `UserData` is the type of the supplementary arguments for the
+///   right-hand-sides, shared by the forward and backward problems. If
+///   unused, should be `()`.
+///
+/// - `N` is the "problem size", that is the dimension of the state space.
+pub struct Solver<UserData, F, const N: usize> {
+    id: usize,
+    core: SolverCore,
+    y0: NVectorSerialHeapAllocated<N>,
+    atol: AbsTolerance<N>,
+    user_data: Pin<Box<WrappingUserData<UserData, F, (), N>>>,
+    /// The pointer to `user_data` given to cvode, from which the pointers of
+    /// the backward problems and the mutable accesses are derived, so that
+    /// they do not invalidate each other.
+    forward_data: *mut WrappingUserData<UserData, F, (), N>,
+    /// The user data of the backward problem `which` is `backward[which]`,
+    /// which is `None` if cvode created it but failed to initialize it.
+    backward: Vec<Option<Pin<Box<BackwardUserData<UserData, N>>>>>,
+}
+
+extern "C" fn wrap_f_b<UserData, const N: usize>(
+    t: Realtype,
+    y: *const NVectorSerial<N>,
+    y_b: *const NVectorSerialDyn,
+    y_b_dot: *mut NVectorSerialDyn,
+    data: *mut BackwardUserData<UserData, N>,
+) -> c_int {
+    let y = unsafe { &*y }.as_slice();
+    let y_b = unsafe { &*y_b }.as_slice();
+    let y_b_dot = unsafe { &mut *y_b_dot }.as_slice_mut();
+    let BackwardUserData {
+        actual_user_data,
+        failure,
+        f_b,
+        ..
+    } = unsafe { &mut *data };
+    let (data, failure) = unsafe { (&**actual_user_data, &**failure) };
+    failure.catch(t, || f_b(t, y, y_b, y_b_dot, data))
+}
+
+extern "C" fn wrap_f_qb<UserData, const N: usize>(
+    t: Realtype,
+    y: *const NVectorSerial<N>,
+    y_b: *const NVectorSerialDyn,
+    q_b_dot: *mut NVectorSerialDyn,
+    data: *mut BackwardUserData<UserData, N>,
+) -> c_int {
+    let y = unsafe { &*y }.as_slice();
+    let y_b = unsafe { &*y_b }.as_slice();
+    let q_b_dot = unsafe { &mut *q_b_dot }.as_slice_mut();
+    let BackwardUserData {
+        actual_user_data,
+        failure,
+        f_qb,
+        ..
+    } = unsafe { &mut *data };
+    let (data, failure) = unsafe { (&**actual_user_data, &**failure) };
+    failure.catch(t, || match f_qb {
+        Some(f_qb) => f_qb(t, y, y_b, q_b_dot, data),
+        None => RhsResult::NonRecoverableError(1),
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+extern "C" fn wrap_jac_b<UserData, const N: usize>(
+    t: Realtype,
+    y: *const NVectorSerial<N>,
+    y_b: *const NVectorSerialDyn,
+    fy_b: *const NVectorSerialDyn,
+    jac: SUNMatrix,
+    data: *mut BackwardUserData<UserData, N>,
+    _tmp1: *const NVectorSerialDyn,
+    _tmp2: *const NVectorSerialDyn,
+    _tmp3: *const NVectorSerialDyn,
+) -> c_int {
+    let y = unsafe { &*y }.as_slice();
+    let y_b = unsafe { &*y_b }.as_slice();
+    let fy_b = unsafe { &*fy_b }.as_slice();
+    let BackwardUserData {
+        actual_user_data,
+        failure,
+        jacobian,
+        ..
+    } = unsafe { &mut *data };
+    let (data, failure) = unsafe { (&**actual_user_data, &**failure) };
+    failure.catch(t, || match jacobian {
+        Some(jac_fn) => jac_fn(t, y, y_b, fy_b, jac, data),
+        None => RhsResult::NonRecoverableError(1),
+    })
+}
+
+impl<UserData, F, const N: usize> Solver<UserData, F, N>
+where
+    F: FnMut(Realtype, &[Realtype; N], &mut [Realtype; N], &UserData) -> RhsResult,
+{
+    /// Create a new solver, storing a checkpoint of the forward solution
+    /// every `steps_between_checkpoints` steps.
+    ///
+    /// The nonlinear iteration is the Newton iteration, with
+    /// [`LinearSolver::Dense`] as linear solver.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        method: LinearMultistepMethod,
+        f: F,
+        t0: Realtype,
+        y0: &[Realtype; N],
+        rtol: Realtype,
+        atol: AbsTolerance<N>,
+        steps_between_checkpoints: usize,
+        interpolation: AdjointInterpolation,
+        user_data: UserData,
+    ) -> Result<Self> {
+        Self::with_solver(
+            method,
+            LinearSolver::Dense,
+            f,
+            t0,
+            y0,
+            rtol,
+            atol,
+            steps_between_checkpoints,
+            interpolation,
+            user_data,
+        )
+    }
+
+    /// Create a new solver, storing a checkpoint of the forward solution
+    /// every `steps_between_checkpoints` steps.
+    ///
+    /// `solver` is the nonlinear iteration: a [`LinearSolver`] for the Newton
+    /// iteration, or a [`NonlinearSolver`].
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_solver(
+        method: LinearMultistepMethod,
+        solver: impl Into<NonlinearSolver>,
+        f: F,
+        t0: Realtype,
+        y0: &[Realtype; N],
+        rtol: Realtype,
+        atol: AbsTolerance<N>,
+        steps_between_checkpoints: usize,
+        interpolation: AdjointInterpolation,
+        user_data: UserData,
+    ) -> Result<Self> {
+        let y0 = NVectorSerialHeapAllocated::new_from(y0);
+        let core = SolverCore::new(method, solver.into(), unsafe { y0.as_raw() }, N)?;
+        let mut user_data = Box::pin(WrappingUserData {
+            actual_user_data: user_data,
+            f,
+            sensi: (),
+            callbacks: Callbacks::new(),
+            failure: CallbackFailure::default(),
+        });
+        let forward_data = unsafe { user_data.as_mut().get_unchecked_mut() } as *mut _;
+        let res = Solver {
+            id: NEXT_SOLVER_ID.fetch_add(1, Ordering::Relaxed),
+            core,
+            y0,
+            atol,
+            user_data,
+            forward_data,
+            backward: Vec::new(),
+        };
+        {
+            let fn_ptr = wrap_f::<UserData, F, (), N> as extern "C" fn(_, _, _, _) -> _;
+            res.core.init(
+                Some(unsafe { std::mem::transmute(fn_ptr) }),
+                t0,
+                unsafe { res.y0.as_raw() },
+                res.forward_data as _,
+            )?;
+        }
+        res.core.capture(|| res.atol.set(res.core.mem, rtol))?;
+        res.core
+            .adj_init(steps_between_checkpoints, interpolation)?;
+        Ok(res)
+    }
+
+    /// Returns the user data passed to the functions called by cvode.
+    pub fn user_data(&self) -> &UserData {
+        &self.user_data.actual_user_data
+    }
+
+    /// Returns the user data passed to the functions called by cvode, to be
+    /// changed between steps.
+    pub fn user_data_mut(&mut self) -> &mut UserData {
+        unsafe { &mut (*self.forward_data).actual_user_data }
+    }
+
+    /// Returns the statistics of the integration of the forward problem.
+    pub fn stats(&self) -> Result<IntegratorStats> {
+        self.core.stats()
+    }
+
+    /// Sets the function computing the Jacobian of the right-hand side of the
+    /// forward problem, when using
+    /// [`LinearSolver::Dense`](crate::LinearSolver::Dense).
+    ///
+    /// See [`GenericSolver::set_dense_jacobian`](crate::GenericSolver::set_dense_jacobian).
+    pub fn set_dense_jacobian<J>(&mut self, jac: J) -> Result<()>
+    where
+        J: FnMut(
+                Realtype,
+                &[Realtype; N],
+                &[Realtype; N],
+                &mut DenseMatrixView<N>,
+                &UserData,
+            ) -> RhsResult
+            + 'static,
+    {
+        Jacobian::Dense(Box::new(jac)).register(
+            self.core.mem,
+            self.core.linear_solver("CVodeSetJacFn")?,
+            unsafe { &mut *self.forward_data },
+        )
+    }
+
+    /// Sets the function computing the Jacobian of the right-hand side of the
+    /// forward problem, when using
+    /// [`LinearSolver::Band`](crate::LinearSolver::Band).
+    ///
+    /// See [`GenericSolver::set_band_jacobian`](crate::GenericSolver::set_band_jacobian).
+    pub fn set_band_jacobian<J>(&mut self, jac: J) -> Result<()>
+    where
+        J: FnMut(
+                Realtype,
+                &[Realtype; N],
+                &[Realtype; N],
+                &mut BandMatrixView<N>,
+                &UserData,
+            ) -> RhsResult
+            + 'static,
+    {
+        Jacobian::Band(Box::new(jac)).register(
+            self.core.mem,
+            self.core.linear_solver("CVodeSetJacFn")?,
+            unsafe { &mut *self.forward_data },
+        )
+    }
+
+    /// Sets the limits of the integration of the forward problem which are
+    /// set in `limits`, the other ones being left unchanged.
+    pub fn set_limits(&mut self, limits: &Limits) -> Result<()> {
+        self.core.set_limits(limits)
+    }
+
+    /// Sets the stop time, past which the solver never integrates the
+    /// forward problem whatever the [`StepKind`], until it is cleared with
+    /// [`Solver::clear_stop_time`]. A forward step ending at it returns
+    /// [`StepOutcome::StopTimeReached`].
+    pub fn set_stop_time(&mut self, tstop: Realtype) -> Result<()> {
+        self.core.set_stop_time(tstop)
+    }
+
+    /// Clears the stop time set with [`Solver::set_stop_time`].
+    pub fn clear_stop_time(&mut self) {
+        self.core.clear_stop_time()
+    }
+
+    /// Takes a step of the forward problem according to `step_kind` (see
+    /// [`StepKind`]), storing the checkpoints needed by the backward
+    /// problems.
+    ///
+    /// Returns a tuple `(t_out,&y(t_out),outcome)` where `t_out` is the time
+    /// reached by the solver as dictated by `step_kind`, `y(t_out)` is an
+    /// array of the state variables at that time, and `outcome` tells how the
+    /// step ended.
+    ///
+    /// The failures of the functions called by cvode are reported as
+    /// described in the [crate documentation](crate#functions-called-by-cvode).
+    pub fn step(
+        &mut self,
+        tout: Realtype,
+        step_kind: StepKind,
+    ) -> Result<(Realtype, &[Realtype; N], StepOutcome)> {
+        let res = self
+            .core
+            .step_forward(tout, unsafe { self.y0.as_raw() }, step_kind);
+        let (tret, outcome) = self.user_data.failure.finish(res)?;
+        Ok((tret, self.y0.as_slice(), outcome))
+    }
+
+    /// Adds a backward problem of size `NB`, starting from the final state
+    /// `(tb0, yb0)`, where `tb0` must lie within the interval integrated by
+    /// the forward problem.
+    ///
+    /// The right-hand side `f_b` takes as arguments
+    /// `(t, y(t), yb, &mut ybdot, user_data)`, where `y(t)` is the
+    /// interpolated solution of the forward problem.
+    ///
+    /// The nonlinear iteration of the backward problem is the Newton
+    /// iteration, with [`LinearSolver::Dense`] as linear solver.
+    pub fn add_backward<FB, const NB: usize>(
+        &mut self,
+        method: LinearMultistepMethod,
+        f_b: FB,
+        tb0: Realtype,
+        yb0: &[Realtype; NB],
+        rtol: Realtype,
+        atol: AbsTolerance<NB>,
+    ) -> Result<BackwardProblem<NB>>
+    where
+        FB: FnMut(
+                Realtype,
+                &[Realtype; N],
+                &[Realtype; NB],
+                &mut [Realtype; NB],
+                &UserData,
+            ) -> RhsResult
+            + 'static,
+    {
+        self.add_backward_with_solver(method, LinearSolver::Dense, f_b, tb0, yb0, rtol, atol)
+    }
+
+    /// Adds a backward problem as [`Solver::add_backward`], whose nonlinear
+    /// iteration is `solver`: a [`LinearSolver`] for the Newton iteration, or
+    /// a [`NonlinearSolver`].
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_backward_with_solver<FB, const NB: usize>(
+        &mut self,
+        method: LinearMultistepMethod,
+        solver: impl Into<NonlinearSolver>,
+        mut f_b: FB,
+        tb0: Realtype,
+        yb0: &[Realtype; NB],
+        rtol: Realtype,
+        atol: AbsTolerance<NB>,
+    ) -> Result<BackwardProblem<NB>>
+    where
+        FB: FnMut(
+                Realtype,
+                &[Realtype; N],
+                &[Realtype; NB],
+                &mut [Realtype; NB],
+                &UserData,
+            ) -> RhsResult
+            + 'static,
+    {
+        let mut data = Box::pin(BackwardUserData {
+            actual_user_data: unsafe { std::ptr::addr_of!((*self.forward_data).actual_user_data) },
+            failure: unsafe { std::ptr::addr_of!((*self.forward_data).failure) },
+            f_b: Box::new(
+                move |t, y, y_b: &[Realtype], y_b_dot: &mut [Realtype], user_data| {
+                    f_b(
+                        t,
+                        y,
+                        y_b.try_into().unwrap(),
+                        y_b_dot.try_into().unwrap(),
+                        user_data,
+                    )
+                },
+            ),
+            jacobian: None,
+            f_qb: None,
+            quadrature_size: 0,
+        });
+        let yb0 = NVectorSerialHeapAllocated::new_from(yb0);
+        let fn_ptr = wrap_f_b::<UserData, N> as extern "C" fn(_, _, _, _, _) -> _;
+        let which = self.core.init_backward(
+            method,
+            solver.into(),
+            Some(unsafe { std::mem::transmute(fn_ptr) }),
+            tb0,
+            unsafe { yb0.as_raw() },
+            NB,
+            unsafe { data.as_mut().get_unchecked_mut() } as *mut _ as _,
+        )?;
+        self.backward.resize_with(which as usize, || None);
+        self.backward.push(Some(data));
+        self.core
+            .capture(|| atol.set_backward(self.core.mem, which, rtol))?;
+        Ok(BackwardProblem {
+            solver_id: self.id,
+            which,
+        })
+    }
+
+    /// Returns the index of `problem` in cvode, or an error on behalf of
+    /// `func_id` if it was not added to this solver.
+    fn which<const NB: usize>(
+        &self,
+        problem: BackwardProblem<NB>,
+        func_id: &'static str,
+    ) -> Result<c_int> {
+        if problem.solver_id != self.id {
+            return Err(Error::IllegalInput {
+                func_id,
+                reason: "the backward problem was added to another solver",
+            });
+        }
+        Ok(problem.which)
+    }
+
+    fn set_backward_jacobian(
+        &mut self,
+        which: c_int,
+        jacobian: BackwardJacobianFn<UserData, N>,
+    ) -> Result<()> {
+        // The problems returned by `add_backward_with_solver` have been
+        // initialized.
+        let data = self.backward[which as usize].as_mut().unwrap();
+        unsafe { data.as_mut().get_unchecked_mut() }.jacobian = Some(jacobian);
+        let fn_ptr = wrap_jac_b::<UserData, N> as extern "C" fn(_, _, _, _, _, _, _, _, _) -> _;
+        self.core
+            .set_backward_jac_fn(which, Some(unsafe { std::mem::transmute(fn_ptr) }))
+    }
+
+    /// Sets the function computing the Jacobian of the right-hand side of
+    /// the backward problem `problem`, when it uses
+    /// [`LinearSolver::Dense`](crate::LinearSolver::Dense).
+    ///
+    /// The function takes as arguments `(t, y(t), yb, fb(t, y, yb), jac,
+    /// user_data)`, and must fill `jac` with the Jacobian `dfb/dyb`.
+    pub fn set_backward_dense_jacobian<J, const NB: usize>(
+        &mut self,
+        problem: BackwardProblem<NB>,
+        mut jac: J,
+    ) -> Result<()>
+    where
+        J: FnMut(
+                Realtype,
+                &[Realtype; N],
+                &[Realtype; NB],
+                &[Realtype; NB],
+                &mut DenseMatrixView<NB>,
+                &UserData,
+            ) -> RhsResult
+            + 'static,
+    {
+        let which = self.which(problem, "CVodeSetJacFnB")?;
+        if !matches!(
+            self.core.backward_linear_solver(which, "CVodeSetJacFnB")?,
+            LinearSolver::Dense
+        ) {
+            return Err(Error::IllegalInput {
+                func_id: "CVodeSetJacFnB",
+                reason: "the Jacobian function does not match the matrix of the linear solver",
+            });
+        }
+        self.set_backward_jacobian(
+            which,
+            Box::new(
+                move |t,
+                      y,
+                      y_b: &[Realtype],
+                      fy_b: &[Realtype],
+                      jac_matrix: SUNMatrix,
+                      user_data| {
+                    jac(
+                        t,
+                        y,
+                        y_b.try_into().unwrap(),
+                        fy_b.try_into().unwrap(),
+                        unsafe { &mut *(jac_matrix as *mut DenseMatrixView<NB>) },
+                        user_data,
+                    )
+                },
+            ),
+        )
+    }
+
+    /// Sets the function computing the Jacobian of the right-hand side of
+    /// the backward problem `problem`, when it uses
+    /// [`LinearSolver::Band`](crate::LinearSolver::Band).
+    ///
+    /// See [`Solver::set_backward_dense_jacobian`], only the elements in the
+    /// band of `jac` can be accessed.
+    pub fn set_backward_band_jacobian<J, const NB: usize>(
+        &mut self,
+        problem: BackwardProblem<NB>,
+        mut jac: J,
+    ) -> Result<()>
+    where
+        J: FnMut(
+                Realtype,
+                &[Realtype; N],
+                &[Realtype; NB],
+                &[Realtype; NB],
+                &mut BandMatrixView<NB>,
+                &UserData,
+            ) -> RhsResult
+            + 'static,
+    {
+        let which = self.which(problem, "CVodeSetJacFnB")?;
+        if !matches!(
+            self.core.backward_linear_solver(which, "CVodeSetJacFnB")?,
+            LinearSolver::Band { .. }
+        ) {
+            return Err(Error::IllegalInput {
+                func_id: "CVodeSetJacFnB",
+                reason: "the Jacobian function does not match the matrix of the linear solver",
+            });
+        }
+        self.set_backward_jacobian(
+            which,
+            Box::new(
+                move |t,
+                      y,
+                      y_b: &[Realtype],
+                      fy_b: &[Realtype],
+                      jac_matrix: SUNMatrix,
+                      user_data| {
+                    jac(
+                        t,
+                        y,
+                        y_b.try_into().unwrap(),
+                        fy_b.try_into().unwrap(),
+                        unsafe { &mut *(jac_matrix as *mut BandMatrixView<NB>) },
+                        user_data,
+                    )
+                },
+            ),
+        )
+    }
+
+    /// Adds to the backward problem `problem` the quadrature of size `NQB`
+    /// of `f_qb`, starting from `yqb0`. It is integrated along with the
+    /// backward problem, and included in its error control with the
+    /// tolerances `rtol` and `atol`.
+    ///
+    /// The right-hand side `f_qb` takes as arguments
+    /// `(t, y(t), yb, &mut qbdot, user_data)`. The quadrature is typically
+    /// the integral giving the gradient of a functional with respect to the
+    /// parameters, which is then given by [`Solver::backward_quadrature`].
+    #[allow(clippy::too_many_arguments)]
+    pub fn set_backward_quadrature<FQB, const NB: usize, const NQB: usize>(
+        &mut self,
+        problem: BackwardProblem<NB>,
+        mut f_qb: FQB,
+        yqb0: &[Realtype; NQB],
+        rtol: Realtype,
+        atol: AbsTolerance<NQB>,
+    ) -> Result<()>
+    where
+        FQB: FnMut(
+                Realtype,
+                &[Realtype; N],
+                &[Realtype; NB],
+                &mut [Realtype; NQB],
+                &UserData,
+            ) -> RhsResult
+            + 'static,
+    {
+        let which = self.which(problem, "CVodeQuadInitB")?;
+        // The problems returned by `add_backward_with_solver` have been
+        // initialized.
+        let data = self.backward[which as usize].as_mut().unwrap();
+        if data.quadrature_size != 0 {
+            return Err(Error::IllegalInput {
+                func_id: "CVodeQuadInitB",
+                reason: "the backward problem already has a quadrature",
+            });
+        }
+        let data = unsafe { data.as_mut().get_unchecked_mut() };
+        data.f_qb = Some(Box::new(
+            move |t, y, y_b: &[Realtype], q_b_dot: &mut [Realtype], user_data| {
+                f_qb(
+                    t,
+                    y,
+                    y_b.try_into().unwrap(),
+                    q_b_dot.try_into().unwrap(),
+                    user_data,
+                )
+            },
+        ));
+        let yqb0 = NVectorSerialHeapAllocated::new_from(yqb0);
+        let fn_ptr = wrap_f_qb::<UserData, N> as extern "C" fn(_, _, _, _, _) -> _;
+        self.core.init_backward_quad(
+            which,
+            Some(unsafe { std::mem::transmute(fn_ptr) }),
+            unsafe { yqb0.as_raw() },
+        )?;
+        data.quadrature_size = NQB;
+        self.core
+            .capture(|| atol.set_backward_quad(self.core.mem, which, rtol))
+    }
+
+    /// Returns the statistics of the integration of the backward problem
+    /// `problem`.
+    pub fn backward_stats<const NB: usize>(
+        &self,
+        problem: BackwardProblem<NB>,
+    ) -> Result<IntegratorStats> {
+        self.core
+            .backward_stats(self.which(problem, "CVodeGetAdjCVodeBmem")?)
+    }
+
+    /// Integrates all the backward problems towards `tb_out` according to
+    /// `step_kind`, which must be [`StepKind::Normal`] or
+    /// [`StepKind::OneStep`]. Their states are then given by
+    /// [`Solver::backward_state`].
+    ///
+    /// Panics and failures of the functions called by cvode are reported as
+    /// by [`Solver::step`]. cvode does not tell which backward problem
+    /// failed, so the time of an [`Error::StepFailed`] is the one reached by
+    /// the backward problem farthest from `tb_out`.
+    pub fn step_backward(&mut self, tb_out: Realtype, step_kind: StepKind) -> Result<()> {
+        let res = self.core.step_backward(tb_out, step_kind);
+        self.user_data.failure.finish(res)
+    }
+
+    /// Returns the time reached by the backward problem `problem`, and its
+    /// state at that time.
+    pub fn backward_state<const NB: usize>(
+        &self,
+        problem: BackwardProblem<NB>,
+    ) -> Result<(Realtype, [Realtype; NB])> {
+        let which = self.which(problem, "CVodeGetB")?;
+        let y_b = NVectorSerialHeapAllocated::<NB>::new();
+        let tret = self.core.get_backward(which, unsafe { y_b.as_raw() })?;
+        Ok((tret, *y_b.as_slice()))
+    }
+
+    /// Returns the time reached by the backward problem `problem`, and its
+    /// quadrature of size `NQB` at that time, set with
+    /// [`Solver::set_backward_quadrature`].
+    pub fn backward_quadrature<const NB: usize, const NQB: usize>(
+        &self,
+        problem: BackwardProblem<NB>,
+    ) -> Result<(Realtype, [Realtype; NQB])> {
+        let which = self.which(problem, "CVodeGetQuadB")?;
+        if !matches!(&self.backward[which as usize], Some(data) if data.quadrature_size == NQB && NQB > 0)
+        {
+            return Err(Error::IllegalInput {
+                func_id: "CVodeGetQuadB",
+                reason: "the backward problem does not have a quadrature of this size",
+            });
+        }
+        let q_b = NVectorSerialHeapAllocated::<NQB>::new();
+        let tret = self
+            .core
+            .get_backward_quad(which, unsafe { q_b.as_raw() })?;
+        Ok((tret, *q_b.as_slice()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `y' = -p * y`, whose adjoint problem for `G = y(t_final)` is augmented
+    /// with the quadrature of `dG/dp`.
+    fn f(_t: Realtype, y: &[Realtype; 1], ydot: &mut [Realtype; 1], p: &Realtype) -> RhsResult {
+        *ydot = [-p * y[0]];
+        RhsResult::Ok
+    }
+
+    fn f_b(
+        _t: Realtype,
+        y: &[Realtype; 1],
+        y_b: &[Realtype; 2],
+        y_b_dot: &mut [Realtype; 2],
+        p: &Realtype,
+    ) -> RhsResult {
+        *y_b_dot = [p * y_b[0], y[0] * y_b[0]];
+        RhsResult::Ok
+    }
+
+    #[test]
+    fn create() {
+        let mut solver = Solver::new(
+            LinearMultistepMethod::Bdf,
+            f,
+            0.,
+            &[1.],
+            1e-8,
+            AbsTolerance::scalar(1e-10),
+            50,
+            AdjointInterpolation::Hermite,
+            1.,
+        )
+        .unwrap();
+        let (_tret, &[y], _outcome) = solver.step(1., StepKind::Normal).unwrap();
+        assert!((y - (-1f64).exp()).abs() < 1e-6);
+        let problem = solver
+            .add_backward(
+                LinearMultistepMethod::Bdf,
+                f_b,
+                1.,
+                &[1., 0.],
+                1e-8,
+                AbsTolerance::scalar(1e-10),
+            )
+            .unwrap();
+        solver
+            .set_backward_dense_jacobian(problem, |_t, y, _y_b, _fy_b, jac, p| {
+                jac[(0, 0)] = *p;
+                jac[(1, 0)] = y[0];
+                RhsResult::Ok
+            })
+            .unwrap();
+        assert!(solver
+            .set_backward_band_jacobian(problem, |_t, _y, _y_b, _fy_b, _jac, _p| RhsResult::Ok)
+            .is_err());
+        assert!(solver.step_backward(0., StepKind::NormalTstop).is_err());
+        solver.step_backward(0., StepKind::Normal).unwrap();
+        let (tret, [dg_dy0, dg_dp]) = solver.backward_state(problem).unwrap();
+        assert_eq!(tret, 0.);
+        assert!((dg_dy0 - (-1f64).exp()).abs() < 1e-5);
+        assert!((dg_dp + (-1f64).exp()).abs() < 1e-5);
+        assert!(solver.backward_stats(problem).unwrap().num_steps > 0);
+    }
+
+    #[test]
+    fn backward_quadrature() {
+        let mut solver = Solver::new(
+            LinearMultistepMethod::Bdf,
+            f,
+            0.,
+            &[1.],
+            1e-8,
+            AbsTolerance::scalar(1e-10),
+            50,
+            AdjointInterpolation::Hermite,
+            1.,
+        )
+        .unwrap();
+        solver
+            .set_dense_jacobian(|_t, _y, _fy, jac: &mut DenseMatrixView<1>, p| {
+                jac[(0, 0)] = -p;
+                RhsResult::Ok
+            })
+            .unwrap();
+        solver.step(1., StepKind::Normal).unwrap();
+        assert!(solver.stats().unwrap().linear_solver.unwrap().num_jac_evals > 0);
+        let problem = solver
+            .add_backward(
+                LinearMultistepMethod::Bdf,
+                |_t, _y, y_b: &[Realtype; 1], y_b_dot: &mut [Realtype; 1], p| {
+                    *y_b_dot = [p * y_b[0]];
+                    RhsResult::Ok
+                },
+                1.,
+                &[1.],
+                1e-8,
+                AbsTolerance::scalar(1e-10),
+            )
+            .unwrap();
+        assert!(solver.backward_quadrature::<1, 1>(problem).is_err());
+        solver
+            .set_backward_quadrature(
+                problem,
+                |_t, y, y_b, q_b_dot: &mut [Realtype; 1], _p| {
+                    *q_b_dot = [y[0] * y_b[0]];
+                    RhsResult::Ok
+                },
+                &[0.],
+                1e-8,
+                AbsTolerance::scalar(1e-10),
+            )
+            .unwrap();
+        assert!(solver
+            .set_backward_quadrature(
+                problem,
+                |_t, _y, _y_b, _q_b_dot: &mut [Realtype; 1], _p| RhsResult::Ok,
+                &[0.],
+                1e-8,
+                AbsTolerance::scalar(1e-10),
+            )
+            .is_err());
+        solver.step_backward(0., StepKind::Normal).unwrap();
+        let (_tret, [dg_dy0]) = solver.backward_state(problem).unwrap();
+        assert!((dg_dy0 - (-1f64).exp()).abs() < 1e-5);
+        let (tret, [dg_dp]) = solver.backward_quadrature(problem).unwrap();
+        assert_eq!(tret, 0.);
+        assert!((dg_dp + (-1f64).exp()).abs() < 1e-5);
+        assert!(solver.backward_quadrature::<1, 2>(problem).is_err());
+    }
+
+    #[test]
+    fn backward_band_jacobian() {
+        let mut solver = Solver::new(
+            LinearMultistepMethod::Bdf,
+            f,
+            0.,
+            &[1.],
+            1e-8,
+            AbsTolerance::scalar(1e-10),
+            50,
+            AdjointInterpolation::Polynomial,
+            1.,
+        )
+        .unwrap();
+        solver.step(1., StepKind::Normal).unwrap();
+        let problem = solver
+            .add_backward_with_solver(
+                LinearMultistepMethod::Bdf,
+                LinearSolver::Band {
+                    upper_bandwidth: 0,
+                    lower_bandwidth: 1,
+                },
+                f_b,
+                1.,
+                &[1., 0.],
+                1e-8,
+                AbsTolerance::vector(&[1e-10; 2]),
+            )
+            .unwrap();
+        assert!(solver
+            .set_backward_dense_jacobian(problem, |_t, _y, _y_b, _fy_b, _jac, _p| RhsResult::Ok)
+            .is_err());
+        solver
+            .set_backward_band_jacobian(problem, |_t, y, _y_b, _fy_b, jac, p| {
+                jac[(0, 0)] = *p;
+                jac[(1, 0)] = y[0];
+                RhsResult::Ok
+            })
+            .unwrap();
+        assert!(solver.step_backward(0., StepKind::OneStepTstop).is_err());
+        solver.step_backward(0., StepKind::Normal).unwrap();
+        let (_tret, [dg_dy0, dg_dp]) = solver.backward_state(problem).unwrap();
+        assert!((dg_dy0 - (-1f64).exp()).abs() < 1e-5);
+        assert!((dg_dp + (-1f64).exp()).abs() < 1e-5);
+        assert!(
+            solver
+                .backward_stats(problem)
+                .unwrap()
+                .linear_solver
+                .unwrap()
+                .num_jac_evals
+                > 0
+        );
+    }
+
+    #[test]
+    fn backward_failure() {
+        let mut solver = Solver::new(
+            LinearMultistepMethod::Bdf,
+            f,
+            0.,
+            &[1.],
+            1e-8,
+            AbsTolerance::scalar(1e-10),
+            50,
+            AdjointInterpolation::Hermite,
+            1.,
+        )
+        .unwrap();
+        solver.step(1., StepKind::Normal).unwrap();
+        solver
+            .add_backward(
+                LinearMultistepMethod::Bdf,
+                |t, y, y_b, y_b_dot: &mut [Realtype; 2], p| {
+                    if t < 0.5 {
+                        return RhsResult::NonRecoverableError(1);
+                    }
+                    f_b(t, y, y_b, y_b_dot, p)
+                },
+                1.,
+                &[1., 0.],
+                1e-8,
+                AbsTolerance::scalar(1e-10),
+            )
+            .unwrap();
+        match solver.step_backward(0., StepKind::Normal) {
+            Err(Error::StepFailed {
+                t,
+                failure: crate::CvodeFailure::RhsFuncFail,
+                message: Some(_),
+            }) => assert!((0.5..1.).contains(&t)),
+            _ => panic!("the backward step should fail"),
+        }
+    }
+
+    #[test]
+    fn foreign_backward_problem() {
+        let new_solver = || {
+            let mut solver = Solver::new(
+                LinearMultistepMethod::Bdf,
+                f,
+                0.,
+                &[1.],
+                1e-8,
+                AbsTolerance::scalar(1e-10),
+                50,
+                AdjointInterpolation::Hermite,
+                1.,
+            )
+            .unwrap();
+            solver.step(1., StepKind::Normal).unwrap();
+            solver
+        };
+        let mut solver = new_solver();
+        let mut other_solver = new_solver();
+        let problem = solver
+            .add_backward(
+                LinearMultistepMethod::Bdf,
+                f_b,
+                1.,
+                &[1., 0.],
+                1e-8,
+                AbsTolerance::scalar(1e-10),
+            )
+            .unwrap();
+        // Without any backward problem in the other solver.
+        assert!(other_solver.backward_state(problem).is_err());
+        assert!(other_solver.backward_stats(problem).is_err());
+        assert!(other_solver
+            .set_backward_dense_jacobian(problem, |_t, _y, _y_b, _fy_b, _jac, _p| RhsResult::Ok)
+            .is_err());
+        // With a backward problem of another size at the same index.
+        let other_problem = other_solver
+            .add_backward(
+                LinearMultistepMethod::Bdf,
+                |_t, _y, _y_b: &[Realtype; 1], y_b_dot: &mut [Realtype; 1], _p| {
+                    *y_b_dot = [0.];
+                    RhsResult::Ok
+                },
+                1.,
+                &[1.],
+                1e-8,
+                AbsTolerance::scalar(1e-10),
+            )
+            .unwrap();
+        other_solver.step_backward(0., StepKind::Normal).unwrap();
+        assert!(other_solver.backward_state(problem).is_err());
+        assert!(other_solver
+            .set_backward_dense_jacobian(problem, |_t, _y, _y_b, _fy_b, _jac, _p| RhsResult::Ok)
+            .is_err());
+        assert!(solver.backward_state(other_problem).is_err());
+        assert_eq!(
+            other_solver.backward_state(other_problem).unwrap(),
+            (0., [1.])
+        );
+    }
+}
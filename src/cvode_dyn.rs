@@ -0,0 +1,339 @@
+//! Wrapper around cvode, without sensitivities, for problems whose size is
+//! only known at run time, and the methods shared with the solver with
+//! sensitivities
+
+use std::{os::raw::c_int, pin::Pin};
+
+use crate::{
+    callback_failure::CallbackFailure,
+    solver_core::{forward_to_core, SolverCore},
+    AbsToleranceDyn, BuiltinPreconditioner, Error, Indextype, LinearMultistepMethod, LinearSolver,
+    NVectorSerialDyn, NVectorSerialHeapAllocatedDyn, NonlinearSolver, PreconditionerSide, Realtype,
+    Result, RhsResult, StepKind, StepOutcome,
+};
+
+pub(crate) struct WrappingUserData<UserData, F, S> {
+    pub(crate) actual_user_data: UserData,
+    pub(crate) f: F,
+    pub(crate) sensi: S,
+    pub(crate) failure: CallbackFailure,
+}
+
+/// The ODE solver for problems whose size is only known at run time,
+/// computing the sensitivities described by `S`.
+///
+/// It is used through its aliases
+/// [`SolverNoSensiDyn`](crate::SolverNoSensiDyn) and
+/// [`SolverSensiDyn`](crate::SolverSensiDyn), which provide the constructors
+/// and the steps. The other methods are shared by both.
+///
+/// It works as [`GenericSolver`](crate::GenericSolver), with slices in place
+/// of arrays. The functions called by cvode other than the right-hand sides
+/// are out of its scope: the Jacobian, Jacobian-times-vector, preconditioner
+/// and root functions, as well as the constraints, are only available on
+/// [`GenericSolver`](crate::GenericSolver).
+///
+/// # Type Arguments
+///
+/// - `F` is the type of the right-hand side function
+///
+/// - `UserData` is the type of the supplementary arguments for the
+///   right-hand-side. If unused, should be `()`.
+///
+/// - `S` is `()` without sensitivities, and
+///   [`SensitivitiesDyn`](crate::SensitivitiesDyn) with sensitivities.
+pub struct GenericSolverDyn<UserData, F, S> {
+    pub(crate) core: SolverCore,
+    pub(crate) y0: NVectorSerialHeapAllocatedDyn,
+    atol: AbsToleranceDyn,
+    pub(crate) user_data: Pin<Box<WrappingUserData<UserData, F, S>>>,
+}
+
+/// The ODE solver without sensitivities, for problems whose size is only
+/// known at run time.
+///
+/// # Type Arguments
+///
+/// - `F` is the type of the right-hand side function
+///
+/// - `UserData` is the type of the supplementary arguments for the
+///   right-hand-side. If unused, should be `()`.
+pub type Solver<UserData, F> = GenericSolverDyn<UserData, F, ()>;
+
+extern "C" fn wrap_f<UserData, F, S>(
+    t: Realtype,
+    y: *const NVectorSerialDyn,
+    ydot: *mut NVectorSerialDyn,
+    data: *mut WrappingUserData<UserData, F, S>,
+) -> c_int
+where
+    F: FnMut(Realtype, &[Realtype], &mut [Realtype], &UserData) -> RhsResult,
+{
+    let y = unsafe { &*y }.as_slice();
+    let ydot = unsafe { &mut *ydot }.as_slice_mut();
+    let WrappingUserData {
+        actual_user_data: data,
+        f,
+        failure,
+        ..
+    } = unsafe { &mut *data };
+    failure.catch(t, || f(t, y, ydot, data))
+}
+
+extern "C" fn wrap_local_f<UserData, F, S>(
+    _n_local: Indextype,
+    t: Realtype,
+    y: *const NVectorSerialDyn,
+    g: *mut NVectorSerialDyn,
+    data: *mut WrappingUserData<UserData, F, S>,
+) -> c_int
+where
+    F: FnMut(Realtype, &[Realtype], &mut [Realtype], &UserData) -> RhsResult,
+{
+    wrap_f::<UserData, F, S>(t, y, g, data)
+}
+
+impl<UserData, F, S> GenericSolverDyn<UserData, F, S>
+where
+    F: FnMut(Realtype, &[Realtype], &mut [Realtype], &UserData) -> RhsResult,
+{
+    /// Creates a solver and initializes cvode, leaving the initialization of
+    /// the sensitivities to the caller.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn create(
+        method: LinearMultistepMethod,
+        nonlinear_solver: NonlinearSolver,
+        f: F,
+        t0: Realtype,
+        y0: &[Realtype],
+        rtol: Realtype,
+        atol: AbsToleranceDyn,
+        user_data: UserData,
+        sensi: S,
+    ) -> Result<Self> {
+        if y0.is_empty() {
+            return Err(Error::IllegalInput {
+                func_id: "CVodeInit",
+                reason: "the initial state is empty",
+            });
+        }
+        atol.check_size(y0.len())?;
+        let y0 = NVectorSerialHeapAllocatedDyn::new_from(y0);
+        let core = SolverCore::new(method, nonlinear_solver, unsafe { y0.as_raw() }, y0.len())?;
+        let user_data = Box::pin(WrappingUserData {
+            actual_user_data: user_data,
+            f,
+            sensi,
+            failure: CallbackFailure::default(),
+        });
+        let mut res = GenericSolverDyn {
+            core,
+            y0,
+            atol,
+            user_data,
+        };
+        {
+            let fn_ptr = wrap_f::<UserData, F, S> as extern "C" fn(_, _, _, _) -> _;
+            res.core.init(
+                Some(unsafe { std::mem::transmute(fn_ptr) }),
+                t0,
+                unsafe { res.y0.as_raw() },
+                unsafe { res.user_data.as_mut().get_unchecked_mut() } as *mut _ as _,
+            )?;
+        }
+        res.core.capture(|| res.atol.set(res.core.mem, rtol))?;
+        Ok(res)
+    }
+
+    /// Checks that a new initial state `y0` has the size of the problem.
+    pub(crate) fn check_reinit_size(&self, y0: &[Realtype]) -> Result<()> {
+        if y0.len() != self.len() {
+            return Err(Error::IllegalInput {
+                func_id: "CVodeReInit",
+                reason: "the initial state does not have the size of the problem",
+            });
+        }
+        Ok(())
+    }
+
+    /// Takes a step of the state, leaving its sensitivities to the caller.
+    pub(crate) fn step_state(
+        &mut self,
+        tout: Realtype,
+        step_kind: StepKind,
+    ) -> Result<(Realtype, StepOutcome)> {
+        let res = self
+            .core
+            .step(tout, unsafe { self.y0.as_raw() }, step_kind, 0);
+        self.user_data.failure.finish(res)
+    }
+
+    /// Returns the size of the problem.
+    pub fn len(&self) -> usize {
+        self.y0.len()
+    }
+
+    /// Returns whether the problem is empty.
+    pub fn is_empty(&self) -> bool {
+        self.y0.is_empty()
+    }
+
+    forward_to_core!();
+
+    /// Sets a preconditioner provided by cvode, to be used by a
+    /// [`LinearSolver::Krylov`](crate::LinearSolver::Krylov) and applied on `side`.
+    pub fn set_builtin_preconditioner(
+        &mut self,
+        side: PreconditionerSide,
+        preconditioner: BuiltinPreconditioner,
+    ) -> Result<()> {
+        let fn_ptr = wrap_local_f::<UserData, F, S> as extern "C" fn(_, _, _, _, _) -> _;
+        self.core.set_builtin_preconditioner(
+            side,
+            preconditioner,
+            self.y0.len(),
+            Some(unsafe { std::mem::transmute(fn_ptr) }),
+        )
+    }
+
+    /// Returns the `k`-th derivative of the interpolated state at time `t`.
+    ///
+    /// `t` must be within the last internal step taken by the solver, and `k`
+    /// must not exceed the current order of the method.
+    pub fn dky(&self, t: Realtype, k: usize) -> Result<Vec<Realtype>> {
+        let dky = NVectorSerialHeapAllocatedDyn::new(self.len());
+        self.core.dky(t, k, unsafe { dky.as_raw() })?;
+        Ok(dky.as_slice().to_vec())
+    }
+}
+
+impl<UserData, F> GenericSolverDyn<UserData, F, ()>
+where
+    F: FnMut(Realtype, &[Realtype], &mut [Realtype], &UserData) -> RhsResult,
+{
+    /// Create a new solver, whose size is the length of `y0`.
+    ///
+    /// The nonlinear iteration is the Newton iteration, with
+    /// [`LinearSolver::Dense`] as linear solver.
+    pub fn new(
+        method: LinearMultistepMethod,
+        f: F,
+        t0: Realtype,
+        y0: &[Realtype],
+        rtol: Realtype,
+        atol: AbsToleranceDyn,
+        user_data: UserData,
+    ) -> Result<Self> {
+        Self::with_solver(
+            method,
+            LinearSolver::Dense,
+            f,
+            t0,
+            y0,
+            rtol,
+            atol,
+            user_data,
+        )
+    }
+
+    /// Create a new solver, whose size is the length of `y0`.
+    ///
+    /// `solver` is the nonlinear iteration: a [`LinearSolver`] for the Newton
+    /// iteration, or a [`NonlinearSolver`].
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_solver(
+        method: LinearMultistepMethod,
+        solver: impl Into<NonlinearSolver>,
+        f: F,
+        t0: Realtype,
+        y0: &[Realtype],
+        rtol: Realtype,
+        atol: AbsToleranceDyn,
+        user_data: UserData,
+    ) -> Result<Self> {
+        Self::create(method, solver.into(), f, t0, y0, rtol, atol, user_data, ())
+    }
+
+    /// Reinitializes the solver with a new initial state, keeping all the
+    /// allocations and options of the solver.
+    pub fn reinit(&mut self, t0: Realtype, y0: &[Realtype]) -> Result<()> {
+        self.check_reinit_size(y0)?;
+        self.y0.as_slice_mut().copy_from_slice(y0);
+        self.core.reinit(t0, unsafe { self.y0.as_raw() })
+    }
+
+    /// Takes a step according to `step_kind` (see [`StepKind`]).
+    ///
+    /// Returns a tuple `(t_out,&y(t_out),outcome)` where `t_out` is the time
+    /// reached by the solver as dictated by `step_kind`, `y(t_out)` is a
+    /// slice of the state variables at that time, and `outcome` tells how
+    /// the step ended.
+    ///
+    /// The failures of the functions called by cvode are reported as
+    /// described in the [crate documentation](crate#functions-called-by-cvode).
+    pub fn step(
+        &mut self,
+        tout: Realtype,
+        step_kind: StepKind,
+    ) -> Result<(Realtype, &[Realtype], StepOutcome)> {
+        let (tret, outcome) = self.step_state(tout, step_kind)?;
+        Ok((tret, self.y0.as_slice(), outcome))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::RhsResult;
+
+    use super::*;
+
+    fn f(_t: super::Realtype, y: &[Realtype], ydot: &mut [Realtype], _data: &()) -> RhsResult {
+        ydot[0] = y[1];
+        ydot[1] = -y[0];
+        RhsResult::Ok
+    }
+
+    #[test]
+    fn create() {
+        let y0 = [0., 1.];
+        let mut solver = Solver::new(
+            LinearMultistepMethod::Bdf,
+            f,
+            0.,
+            &y0,
+            1e-6,
+            AbsToleranceDyn::vector(&[1e-6; 2]),
+            (),
+        )
+        .unwrap();
+        assert_eq!(solver.len(), 2);
+        let (_tret, y, outcome) = solver.step(1., StepKind::Normal).unwrap();
+        assert!((y[0] - 1f64.sin()).abs() < 1e-4);
+        assert_eq!(outcome, StepOutcome::Reached);
+        assert_eq!(solver.dky(1., 0).unwrap().len(), 2);
+        assert!(solver.reinit(0., &[0.; 3]).is_err());
+        assert!(Solver::new(
+            LinearMultistepMethod::Bdf,
+            f,
+            0.,
+            &y0,
+            1e-6,
+            AbsToleranceDyn::vector(&[1e-6; 3]),
+            (),
+        )
+        .is_err());
+        assert!(Solver::new(
+            LinearMultistepMethod::Bdf,
+            f,
+            0.,
+            &[],
+            1e-6,
+            AbsToleranceDyn::scalar(1e-6),
+            (),
+        )
+        .is_err());
+        assert!(NVectorSerialHeapAllocatedDyn::new_from(&[])
+            .as_slice()
+            .is_empty());
+    }
+}
@@ -1,19 +1,22 @@
 //! Wrapper around cvodeS, with sensitivities
 
-use std::{convert::TryInto, os::raw::c_int, pin::Pin};
-
-use sundials_sys::{SUNLinearSolver, SUNMatrix, CV_STAGGERED};
+use std::os::raw::c_int;
 
 use crate::{
-    check_flag_is_succes, check_non_null, AbsTolerance, CvodeMemoryBlock,
-    CvodeMemoryBlockNonNullPtr, LinearMultistepMethod, NVectorSerial, NVectorSerialHeapAllocated,
-    Realtype, Result, RhsResult, SensiAbsTolerance, StepKind,
+    cvode::{GenericSolver, WrappingUserData},
+    solver_core::forward_to_core,
+    AbsTolerance, LinearMultistepMethod, LinearSolver, NVectorSerial, NVectorSerialHeapAllocated,
+    NonlinearSolver, Realtype, Result, RhsResult, SensiAbsTolerance, StepKind, StepOutcome,
 };
 
-struct WrappingUserData<UserData, F, FS> {
-    actual_user_data: UserData,
-    f: F,
+/// The sensitivities computed by a [`SolverSensi`](crate::SolverSensi): the
+/// sensitivities right-hand side `FS`, and the storage of the `N_SENSI`
+/// sensitivities of a state of size `N`.
+pub struct Sensitivities<FS, const N: usize, const N_SENSI: usize> {
     fs: FS,
+    y_s0: Box<[NVectorSerialHeapAllocated<N>; N_SENSI]>,
+    atol_sens: SensiAbsTolerance<N, N_SENSI>,
+    sensi_out_buffer: [NVectorSerialHeapAllocated<N>; N_SENSI],
 }
 
 /// The ODE solver with sensitivities.
@@ -30,41 +33,8 @@ struct WrappingUserData<UserData, F, FS> {
 /// - `N` is the "problem size", that is the dimension of the state space.
 ///
 /// - `N_SENSI` is the number of sensitivities computed
-pub struct Solver<UserData, F, FS, const N: usize, const N_SENSI: usize> {
-    mem: CvodeMemoryBlockNonNullPtr,
-    y0: NVectorSerialHeapAllocated<N>,
-    y_s0: Box<[NVectorSerialHeapAllocated<N>; N_SENSI]>,
-    sunmatrix: SUNMatrix,
-    linsolver: SUNLinearSolver,
-    atol: AbsTolerance<N>,
-    atol_sens: SensiAbsTolerance<N, N_SENSI>,
-    user_data: Pin<Box<WrappingUserData<UserData, F, FS>>>,
-    sensi_out_buffer: [NVectorSerialHeapAllocated<N>; N_SENSI],
-}
-
-extern "C" fn wrap_f<UserData, F, FS, const N: usize>(
-    t: Realtype,
-    y: *const NVectorSerial<N>,
-    ydot: *mut NVectorSerial<N>,
-    data: *const WrappingUserData<UserData, F, FS>,
-) -> c_int
-where
-    F: Fn(Realtype, &[Realtype; N], &mut [Realtype; N], &UserData) -> RhsResult,
-{
-    let y = unsafe { &*y }.as_slice();
-    let ydot = unsafe { &mut *ydot }.as_slice_mut();
-    let WrappingUserData {
-        actual_user_data: data,
-        f,
-        ..
-    } = unsafe { &*data };
-    let res = f(t, y, ydot, data);
-    match res {
-        RhsResult::Ok => 0,
-        RhsResult::RecoverableError(e) => e as c_int,
-        RhsResult::NonRecoverableError(e) => -(e as c_int),
-    }
-}
+pub type Solver<UserData, F, FS, const N: usize, const N_SENSI: usize> =
+    GenericSolver<UserData, F, Sensitivities<FS, N, N_SENSI>, N>;
 
 extern "C" fn wrap_f_sens<UserData, F, FS, const N: usize, const N_SENSI: usize>(
     _n_s: c_int,
@@ -73,12 +43,12 @@ extern "C" fn wrap_f_sens<UserData, F, FS, const N: usize, const N_SENSI: usize>
     ydot: *const NVectorSerial<N>,
     y_s: *const [*const NVectorSerial<N>; N_SENSI],
     y_sdot: *mut [*mut NVectorSerial<N>; N_SENSI],
-    data: *const WrappingUserData<UserData, F, FS>,
+    data: *mut WrappingUserData<UserData, F, Sensitivities<FS, N, N_SENSI>, N>,
     _tmp1: *const NVectorSerial<N>,
     _tmp2: *const NVectorSerial<N>,
 ) -> c_int
 where
-    FS: Fn(
+    FS: FnMut(
         Realtype,
         &[Realtype; N],
         &[Realtype; N],
@@ -101,21 +71,18 @@ where
     .unwrap();
     let WrappingUserData {
         actual_user_data: data,
-        fs,
+        sensi,
+        failure,
         ..
-    } = unsafe { &*data };
-    let res = fs(t, y, ydot, y_s, y_sdot, data);
-    match res {
-        RhsResult::Ok => 0,
-        RhsResult::RecoverableError(e) => e as c_int,
-        RhsResult::NonRecoverableError(e) => -(e as c_int),
-    }
+    } = unsafe { &mut *data };
+    failure.catch(t, || (sensi.fs)(t, y, ydot, y_s, y_sdot, data))
 }
 
-impl<UserData, F, FS, const N: usize, const N_SENSI: usize> Solver<UserData, F, FS, N, N_SENSI>
+impl<UserData, F, FS, const N: usize, const N_SENSI: usize>
+    GenericSolver<UserData, F, Sensitivities<FS, N, N_SENSI>, N>
 where
-    F: Fn(Realtype, &[Realtype; N], &mut [Realtype; N], &UserData) -> RhsResult,
-    FS: Fn(
+    F: FnMut(Realtype, &[Realtype; N], &mut [Realtype; N], &UserData) -> RhsResult,
+    FS: FnMut(
         Realtype,
         &[Realtype; N],
         &[Realtype; N],
@@ -125,7 +92,10 @@ where
     ) -> RhsResult,
 {
     /// Creates a new solver.
-    #[allow(clippy::clippy::too_many_arguments)]
+    ///
+    /// The nonlinear iteration is the Newton iteration, with
+    /// [`LinearSolver::Dense`] as linear solver.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         method: LinearMultistepMethod,
         f: F,
@@ -138,12 +108,39 @@ where
         atol_sens: SensiAbsTolerance<N, N_SENSI>,
         user_data: UserData,
     ) -> Result<Self> {
-        assert_eq!(y0.len(), N);
-        let mem: CvodeMemoryBlockNonNullPtr = {
-            let mem_maybenull = unsafe { sundials_sys::CVodeCreate(method as c_int) };
-            check_non_null(mem_maybenull as *mut CvodeMemoryBlock, "CVodeCreate")?.into()
-        };
-        let y0 = NVectorSerialHeapAllocated::new_from(y0);
+        Self::with_solver(
+            method,
+            LinearSolver::Dense,
+            f,
+            f_sens,
+            t0,
+            y0,
+            y_s0,
+            rtol,
+            atol,
+            atol_sens,
+            user_data,
+        )
+    }
+
+    /// Creates a new solver.
+    ///
+    /// `solver` is the nonlinear iteration: a [`LinearSolver`] for the Newton
+    /// iteration, or a [`NonlinearSolver`].
+    #[allow(clippy::clippy::too_many_arguments)]
+    pub fn with_solver(
+        method: LinearMultistepMethod,
+        solver: impl Into<NonlinearSolver>,
+        f: F,
+        f_sens: FS,
+        t0: Realtype,
+        y0: &[Realtype; N],
+        y_s0: &[[Realtype; N]; N_SENSI],
+        rtol: Realtype,
+        atol: AbsTolerance<N>,
+        atol_sens: SensiAbsTolerance<N, N_SENSI>,
+        user_data: UserData,
+    ) -> Result<Self> {
         let y_s0 = Box::new(
             array_init::from_iter(
                 y_s0.iter()
@@ -151,155 +148,120 @@ where
             )
             .unwrap(),
         );
-        let matrix = {
-            let matrix = unsafe {
-                sundials_sys::SUNDenseMatrix(N.try_into().unwrap(), N.try_into().unwrap())
-            };
-            check_non_null(matrix, "SUNDenseMatrix")?
-        };
-        let linsolver = {
-            let linsolver = unsafe { sundials_sys::SUNLinSol_Dense(y0.as_raw(), matrix.as_ptr()) };
-            check_non_null(linsolver, "SUNDenseLinearSolver")?
-        };
-        let user_data = Box::pin(WrappingUserData {
-            actual_user_data: user_data,
-            f,
+        let sensi = Sensitivities {
             fs: f_sens,
-        });
-        let res = Solver {
-            mem,
-            y0,
             y_s0,
-            sunmatrix: matrix.as_ptr(),
-            linsolver: linsolver.as_ptr(),
-            atol,
             atol_sens,
-            user_data,
             sensi_out_buffer: array_init::array_init(|_| NVectorSerialHeapAllocated::new()),
         };
-        {
-            let flag = unsafe {
-                sundials_sys::CVodeSetUserData(
-                    mem.as_raw(),
-                    res.user_data.as_ref().get_ref() as *const _ as _,
-                )
-            };
-            check_flag_is_succes(flag, "CVodeSetUserData")?;
-        }
-        {
-            let fn_ptr = wrap_f::<UserData, F, FS, N> as extern "C" fn(_, _, _, _) -> _;
-            let flag = unsafe {
-                sundials_sys::CVodeInit(
-                    mem.as_raw(),
-                    Some(std::mem::transmute(fn_ptr)),
-                    t0,
-                    res.y0.as_raw(),
-                )
-            };
-            check_flag_is_succes(flag, "CVodeInit")?;
-        }
+        let mut res = Self::create(
+            method,
+            solver.into(),
+            f,
+            t0,
+            y0,
+            rtol,
+            atol,
+            user_data,
+            sensi,
+        )?;
         {
             let fn_ptr = wrap_f_sens::<UserData, F, FS, N, N_SENSI>
                 as extern "C" fn(_, _, _, _, _, _, _, _, _) -> _;
-            let flag = unsafe {
-                sundials_sys::CVodeSensInit(
-                    mem.as_raw(),
-                    N_SENSI as c_int,
-                    CV_STAGGERED as _,
-                    Some(std::mem::transmute(fn_ptr)),
-                    res.y_s0.as_ptr() as _,
-                )
-            };
-            check_flag_is_succes(flag, "CVodeSensInit")?;
-        }
-        match &res.atol {
-            &AbsTolerance::Scalar(atol) => {
-                let flag = unsafe { sundials_sys::CVodeSStolerances(mem.as_raw(), rtol, atol) };
-                check_flag_is_succes(flag, "CVodeSStolerances")?;
-            }
-            AbsTolerance::Vector(atol) => {
-                let flag =
-                    unsafe { sundials_sys::CVodeSVtolerances(mem.as_raw(), rtol, atol.as_raw()) };
-                check_flag_is_succes(flag, "CVodeSVtolerances")?;
-            }
-        }
-        match &res.atol_sens {
-            SensiAbsTolerance::Scalar(atol) => {
-                let flag = unsafe {
-                    sundials_sys::CVodeSensSStolerances(mem.as_raw(), rtol, atol.as_ptr() as _)
-                };
-                check_flag_is_succes(flag, "CVodeSensSStolerances")?;
-            }
-            SensiAbsTolerance::Vector(atol) => {
-                let flag = unsafe {
-                    sundials_sys::CVodeSensSVtolerances(mem.as_raw(), rtol, atol.as_ptr() as _)
-                };
-                check_flag_is_succes(flag, "CVodeSensSVtolerances")?;
-            }
-        }
-        {
-            let flag = unsafe {
-                sundials_sys::CVodeSetLinearSolver(
-                    mem.as_raw(),
-                    linsolver.as_ptr(),
-                    matrix.as_ptr(),
-                )
-            };
-            check_flag_is_succes(flag, "CVodeSetLinearSolver")?;
+            res.core.init_sens(
+                N_SENSI,
+                Some(unsafe { std::mem::transmute(fn_ptr) }),
+                res.user_data.sensi.y_s0.as_ptr() as _,
+                unsafe { res.y0.as_raw() },
+            )?;
         }
+        res.core
+            .capture(|| res.user_data.sensi.atol_sens.set(res.core.mem, rtol))?;
         Ok(res)
     }
 
+    /// Reinitializes the solver with a new initial state and new initial
+    /// sensitivities, keeping all the allocations, options and callbacks of
+    /// the solver.
+    pub fn reinit(
+        &mut self,
+        t0: Realtype,
+        y0: &[Realtype; N],
+        y_s0: &[[Realtype; N]; N_SENSI],
+    ) -> Result<()> {
+        *self.y0.as_slice_mut() = *y0;
+        let sensi = unsafe { &mut self.user_data.as_mut().get_unchecked_mut().sensi };
+        for (v, y_s0_i) in sensi.y_s0.iter_mut().zip(y_s0) {
+            *v.as_slice_mut() = *y_s0_i;
+        }
+        self.core.reinit(t0, unsafe { self.y0.as_raw() })?;
+        self.core.reinit_sens(sensi.y_s0.as_ptr() as _)
+    }
+
+    forward_to_core!(sens);
+
+    /// Returns the `k`-th derivative of the interpolated sensitivities at
+    /// time `t`, with the same requirements as [`Self::dky`].
+    pub fn sens_dky(&self, t: Realtype, k: usize) -> Result<[[Realtype; N]; N_SENSI]> {
+        let mut dky: [NVectorSerialHeapAllocated<N>; N_SENSI] =
+            array_init::array_init(|_| NVectorSerialHeapAllocated::new());
+        self.core.sens_dky(t, k, dky.as_mut_ptr() as _)?;
+        Ok(array_init::array_init(|i| *dky[i].as_slice()))
+    }
+
+    /// Returns the `k`-th derivative of the interpolated sensitivity with
+    /// respect to parameter `i` at time `t`, with the same requirements as
+    /// [`Self::dky`].
+    pub fn sens_dky1(&self, t: Realtype, k: usize, i: usize) -> Result<[Realtype; N]> {
+        let dky = NVectorSerialHeapAllocated::<N>::new();
+        self.core
+            .sens_dky1(t, k, i, N_SENSI, unsafe { dky.as_raw() })?;
+        Ok(*dky.as_slice())
+    }
+
     /// Takes a step according to `step_kind` (see [`StepKind`]).
     ///
-    /// Returns a tuple `(t_out,&y(t_out),[&dy_dp(tout)])` where `t_out` is the time
-    /// reached by the solver as dictated by `step_kind`, `y(t_out)` is an
-    /// array of the state variables at that time, and the i-th `dy_dp(tout)` is an array
-    /// of the sensitivities of all variables with respect to parameter i.
+    /// Returns a tuple `(t_out,&y(t_out),[&dy_dp(tout)],outcome)` where `t_out` is the time
+    /// reached by the solver as dictated by `step_kind` or a root, `y(t_out)` is an
+    /// array of the state variables at that time, the i-th `dy_dp(tout)` is an array
+    /// of the sensitivities of all variables with respect to parameter i, and
+    /// `outcome` tells how the step ended.
+    ///
+    /// The failures of the functions called by cvode are reported as
+    /// described in the [crate documentation](crate#functions-called-by-cvode).
     #[allow(clippy::clippy::type_complexity)]
     pub fn step(
         &mut self,
         tout: Realtype,
         step_kind: StepKind,
-    ) -> Result<(Realtype, &[Realtype; N], [&[Realtype; N]; N_SENSI])> {
-        let mut tret = 0.;
-        let flag = unsafe {
-            sundials_sys::CVode(
-                self.mem.as_raw(),
-                tout,
-                self.y0.as_raw(),
-                &mut tret,
-                step_kind as c_int,
-            )
-        };
-        check_flag_is_succes(flag, "CVode")?;
-        let flag = unsafe {
-            sundials_sys::CVodeGetSens(
-                self.mem.as_raw(),
-                &mut tret,
-                self.sensi_out_buffer.as_mut_ptr() as _,
-            )
-        };
-        check_flag_is_succes(flag, "CVodeGetSens")?;
-        let sensi_ptr_array =
-            array_init::from_iter(self.sensi_out_buffer.iter().map(|v| v.as_slice())).unwrap();
-        Ok((tret, self.y0.as_slice(), sensi_ptr_array))
-    }
-}
-
-impl<UserData, F, FS, const N: usize, const N_SENSI: usize> Drop
-    for Solver<UserData, F, FS, N, N_SENSI>
-{
-    fn drop(&mut self) {
-        unsafe { sundials_sys::CVodeFree(&mut self.mem.as_raw()) }
-        unsafe { sundials_sys::SUNLinSolFree(self.linsolver) };
-        unsafe { sundials_sys::SUNMatDestroy(self.sunmatrix) };
+    ) -> Result<(
+        Realtype,
+        &[Realtype; N],
+        [&[Realtype; N]; N_SENSI],
+        StepOutcome,
+    )> {
+        let (tret, outcome) = self.step_state(tout, step_kind)?;
+        let sensi = unsafe { &mut self.user_data.as_mut().get_unchecked_mut().sensi };
+        self.core
+            .get_sens(sensi.sensi_out_buffer.as_mut_ptr() as _)?;
+        let sensi_ptr_array = array_init::from_iter(
+            self.user_data
+                .sensi
+                .sensi_out_buffer
+                .iter()
+                .map(|v| v.as_slice()),
+        )
+        .unwrap();
+        Ok((tret, self.y0.as_slice(), sensi_ptr_array, outcome))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::RhsResult;
+    use crate::{
+        Constraint, DenseMatrixView, KrylovMethod, PreconditionerSide, RhsResult, RootCrossing,
+        RootDirection,
+    };
 
     use super::*;
 
@@ -327,6 +289,33 @@ mod tests {
         RhsResult::Ok
     }
 
+    /// The sensitivities of the state of `f` with respect to its initial
+    /// state.
+    fn fs_initial_state<const N_SENSI: usize>(
+        _t: super::Realtype,
+        _y: &[Realtype; 2],
+        _ydot: &[Realtype; 2],
+        ys: [&[Realtype; 2]; N_SENSI],
+        ysdot: [&mut [Realtype; 2]; N_SENSI],
+        _data: &(),
+    ) -> RhsResult {
+        for (ys_i, ysdot_i) in ys.iter().zip(std::array::IntoIter::new(ysdot)) {
+            *ysdot_i = [ys_i[1], -ys_i[0]];
+        }
+        RhsResult::Ok
+    }
+
+    /// Checks the sensitivities computed with `fs_initial_state` at `t`,
+    /// starting from the identity.
+    fn check_initial_state_sensi(t: Realtype, y_s: [&[Realtype; 2]; 2]) {
+        let expected = [[t.cos(), -t.sin()], [t.sin(), t.cos()]];
+        for (y_s_i, expected_i) in y_s.iter().zip(&expected) {
+            for (a, b) in y_s_i.iter().zip(expected_i) {
+                assert!((a - b).abs() < 1e-3);
+            }
+        }
+    }
+
     #[test]
     fn create() {
         let y0 = [0., 1.];
@@ -345,4 +334,195 @@ mod tests {
         )
         .unwrap();
     }
+
+    #[test]
+    fn fixed_point() {
+        let y0 = [0., 1.];
+        let y_s0 = [[0.; 2]; 2];
+        let mut solver = Solver::with_solver(
+            LinearMultistepMethod::Adams,
+            NonlinearSolver::FixedPoint { anderson_depth: 0 },
+            f,
+            fs,
+            0.,
+            &y0,
+            &y_s0,
+            1e-6,
+            AbsTolerance::scalar(1e-6),
+            SensiAbsTolerance::scalar([1e-6; 2]),
+            (),
+        )
+        .unwrap();
+        let (_tret, y, y_s, _outcome) = solver.step(1., StepKind::Normal).unwrap();
+        assert!((y[0] - 1f64.sin()).abs() < 1e-4);
+        assert_eq!(y_s[0], &[0.; 2]);
+        assert!(solver.sens_stats().unwrap().num_sens_rhs_evals > 0);
+        assert_eq!(solver.sens_dky(1., 0).unwrap(), [[0.; 2]; 2]);
+        assert_eq!(solver.sens_dky1(1., 0, 1).unwrap(), [0.; 2]);
+        assert!(solver.sens_dky1(1., 0, 2).is_err());
+        solver.reinit(0., &y0, &[[1., 0.], [0., 1.]]).unwrap();
+        let (_tret, _y, y_s, _outcome) = solver.step(1., StepKind::Normal).unwrap();
+        assert_eq!(y_s, [&[1., 0.], &[0., 1.]]);
+    }
+
+    #[test]
+    fn dense_jacobian() {
+        let y0 = [0., 1.];
+        let y_s0 = [[1., 0.], [0., 1.]];
+        let mut solver = Solver::new(
+            LinearMultistepMethod::Bdf,
+            f,
+            fs_initial_state,
+            0.,
+            &y0,
+            &y_s0,
+            1e-6,
+            AbsTolerance::scalar(1e-6),
+            SensiAbsTolerance::scalar([1e-6; 2]),
+            (),
+        )
+        .unwrap();
+        solver
+            .set_dense_jacobian(|_t, _y, _fy, jac: &mut DenseMatrixView<2>, _data| {
+                jac[(0, 1)] = 1.;
+                jac[(1, 0)] = -1.;
+                RhsResult::Ok
+            })
+            .unwrap();
+        let (tret, y, y_s, outcome) = solver.step(1., StepKind::Normal).unwrap();
+        assert!((y[0] - 1f64.sin()).abs() < 1e-4);
+        assert_eq!(outcome, StepOutcome::Reached);
+        check_initial_state_sensi(tret, y_s);
+        assert!(solver.stats().unwrap().linear_solver.unwrap().num_jac_evals > 0);
+        assert!(solver.sens_stats().unwrap().num_sens_rhs_evals > 0);
+    }
+
+    #[test]
+    fn preconditioner() {
+        let y0 = [0., 1.];
+        let y_s0 = [[1., 0.], [0., 1.]];
+        let mut solver = Solver::with_solver(
+            LinearMultistepMethod::Bdf,
+            LinearSolver::Krylov {
+                method: KrylovMethod::Sptfqmr,
+                max_krylov_dim: 0,
+                eps_lin: None,
+            },
+            f,
+            fs_initial_state,
+            0.,
+            &y0,
+            &y_s0,
+            1e-6,
+            AbsTolerance::scalar(1e-6),
+            SensiAbsTolerance::scalar([1e-6; 2]),
+            (),
+        )
+        .unwrap();
+        solver
+            .set_preconditioner(
+                PreconditionerSide::Left,
+                |_t, _y, _fy, _jok, _gamma, _data| (false, RhsResult::Ok),
+                |_t,
+                 _y,
+                 _fy,
+                 r: &[Realtype; 2],
+                 z: &mut [Realtype; 2],
+                 _gamma,
+                 _delta,
+                 _lr,
+                 _data| {
+                    *z = *r;
+                    RhsResult::Ok
+                },
+            )
+            .unwrap();
+        let (tret, y, y_s, _outcome) = solver.step(1., StepKind::Normal).unwrap();
+        assert!((y[0] - 1f64.sin()).abs() < 1e-4);
+        check_initial_state_sensi(tret, y_s);
+        assert!(
+            solver
+                .stats()
+                .unwrap()
+                .linear_solver
+                .unwrap()
+                .num_prec_solves
+                > 0
+        );
+    }
+
+    #[test]
+    fn root() {
+        let y0 = [0., 1.];
+        let y_s0 = [[1., 0.], [0., 1.]];
+        let mut solver = Solver::new(
+            LinearMultistepMethod::Adams,
+            f,
+            fs_initial_state,
+            0.,
+            &y0,
+            &y_s0,
+            1e-6,
+            AbsTolerance::scalar(1e-6),
+            SensiAbsTolerance::scalar([1e-6; 2]),
+            (),
+        )
+        .unwrap();
+        solver
+            .set_root_fn(
+                [RootDirection::Increasing],
+                |_t, y, gout: &mut [Realtype; 1], _data| {
+                    *gout = [y[0] - 0.5];
+                    RhsResult::Ok
+                },
+            )
+            .unwrap();
+        let (tret, _y, y_s, outcome) = solver.step(10., StepKind::Normal).unwrap();
+        assert!((tret - std::f64::consts::FRAC_PI_6).abs() < 1e-4);
+        assert_eq!(
+            outcome,
+            StepOutcome::RootFound(vec![Some(RootCrossing::Increasing)])
+        );
+        check_initial_state_sensi(tret, y_s);
+    }
+
+    #[test]
+    fn constraints() {
+        let y0 = [1., 0.];
+        let y_s0 = [[1., 0.]];
+        let mut solver = Solver::new(
+            LinearMultistepMethod::Bdf,
+            |_t, y: &[Realtype; 2], ydot: &mut [Realtype; 2], _data: &()| {
+                *ydot = [-100. * y[0], 100. * y[0]];
+                RhsResult::Ok
+            },
+            |_t,
+             _y: &[Realtype; 2],
+             _ydot: &[Realtype; 2],
+             [ys]: [&[Realtype; 2]; 1],
+             [ysdot]: [&mut [Realtype; 2]; 1],
+             _data: &()| {
+                *ysdot = [-100. * ys[0], 100. * ys[0]];
+                RhsResult::Ok
+            },
+            0.,
+            &y0,
+            &y_s0,
+            1e-4,
+            AbsTolerance::scalar(1e-8),
+            SensiAbsTolerance::scalar([1e-8]),
+            (),
+        )
+        .unwrap();
+        solver
+            .set_constraints(&[Constraint::NonNegative, Constraint::None])
+            .unwrap();
+        for &t in &[0.1, 1., 10.] {
+            let (tret, &[a, b], [y_s], _outcome) = solver.step(t, StepKind::Normal).unwrap();
+            assert!(a >= 0.);
+            assert!((a + b - 1.).abs() < 1e-4);
+            assert!((y_s[0] - (-100. * tret).exp()).abs() < 1e-3);
+            assert!((y_s[0] + y_s[1] - 1.).abs() < 1e-3);
+        }
+    }
 }
@@ -0,0 +1,340 @@
+//! Wrapper around cvodeS, with sensitivities, for problems whose size and
+//! number of sensitivities are only known at run time
+
+use std::os::raw::c_int;
+
+use crate::{
+    cvode_dyn::{GenericSolverDyn, WrappingUserData},
+    solver_core::forward_to_core,
+    AbsToleranceDyn, Error, LinearMultistepMethod, LinearSolver, NVectorSerialDyn,
+    NVectorSerialHeapAllocatedDyn, NonlinearSolver, Realtype, Result, RhsResult,
+    SensiAbsToleranceDyn, StepKind, StepOutcome,
+};
+
+/// The sensitivities computed by a [`SolverSensiDyn`](crate::SolverSensiDyn):
+/// the sensitivities right-hand side `FS`, and the storage of the
+/// sensitivities.
+pub struct SensitivitiesDyn<FS> {
+    fs: FS,
+    y_s0: Vec<NVectorSerialHeapAllocatedDyn>,
+    atol_sens: SensiAbsToleranceDyn,
+    sensi_out_buffer: Vec<NVectorSerialHeapAllocatedDyn>,
+}
+
+/// The ODE solver with sensitivities, for problems whose size and number of
+/// sensitivities are only known at run time.
+///
+/// # Type Arguments
+///
+/// - `F` is the type of the right-hand side function
+///
+/// - `FS` is the type of the sensitivities right-hand side function
+///
+/// - `UserData` is the type of the supplementary arguments for the
+///   right-hand-side. If unused, should be `()`.
+pub type Solver<UserData, F, FS> = GenericSolverDyn<UserData, F, SensitivitiesDyn<FS>>;
+
+extern "C" fn wrap_f_sens<UserData, F, FS>(
+    n_s: c_int,
+    t: Realtype,
+    y: *const NVectorSerialDyn,
+    ydot: *const NVectorSerialDyn,
+    y_s: *const *const NVectorSerialDyn,
+    y_sdot: *const *mut NVectorSerialDyn,
+    data: *mut WrappingUserData<UserData, F, SensitivitiesDyn<FS>>,
+    _tmp1: *const NVectorSerialDyn,
+    _tmp2: *const NVectorSerialDyn,
+) -> c_int
+where
+    FS: FnMut(
+        Realtype,
+        &[Realtype],
+        &[Realtype],
+        &[&[Realtype]],
+        &mut [&mut [Realtype]],
+        &UserData,
+    ) -> RhsResult,
+{
+    let n_s = n_s as usize;
+    let y = unsafe { &*y }.as_slice();
+    let ydot = unsafe { &*ydot }.as_slice();
+    let y_s: Vec<&[Realtype]> = unsafe { std::slice::from_raw_parts(y_s, n_s) }
+        .iter()
+        .map(|&v| unsafe { &*v }.as_slice())
+        .collect();
+    let mut y_sdot: Vec<&mut [Realtype]> = unsafe { std::slice::from_raw_parts(y_sdot, n_s) }
+        .iter()
+        .map(|&v| unsafe { &mut *v }.as_slice_mut())
+        .collect();
+    let WrappingUserData {
+        actual_user_data: data,
+        sensi,
+        failure,
+        ..
+    } = unsafe { &mut *data };
+    failure.catch(t, || (sensi.fs)(t, y, ydot, &y_s, &mut y_sdot, data))
+}
+
+impl<UserData, F, FS> GenericSolverDyn<UserData, F, SensitivitiesDyn<FS>>
+where
+    F: FnMut(Realtype, &[Realtype], &mut [Realtype], &UserData) -> RhsResult,
+    FS: FnMut(
+        Realtype,
+        &[Realtype],
+        &[Realtype],
+        &[&[Realtype]],
+        &mut [&mut [Realtype]],
+        &UserData,
+    ) -> RhsResult,
+{
+    /// Creates a new solver, whose size is the length of `y0` and whose
+    /// number of sensitivities is the length of `y_s0`.
+    ///
+    /// The nonlinear iteration is the Newton iteration, with
+    /// [`LinearSolver::Dense`] as linear solver.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        method: LinearMultistepMethod,
+        f: F,
+        f_sens: FS,
+        t0: Realtype,
+        y0: &[Realtype],
+        y_s0: &[&[Realtype]],
+        rtol: Realtype,
+        atol: AbsToleranceDyn,
+        atol_sens: SensiAbsToleranceDyn,
+        user_data: UserData,
+    ) -> Result<Self> {
+        Self::with_solver(
+            method,
+            LinearSolver::Dense,
+            f,
+            f_sens,
+            t0,
+            y0,
+            y_s0,
+            rtol,
+            atol,
+            atol_sens,
+            user_data,
+        )
+    }
+
+    /// Creates a new solver, whose size is the length of `y0` and whose
+    /// number of sensitivities is the length of `y_s0`.
+    ///
+    /// `solver` is the nonlinear iteration: a [`LinearSolver`] for the Newton
+    /// iteration, or a [`NonlinearSolver`].
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_solver(
+        method: LinearMultistepMethod,
+        solver: impl Into<NonlinearSolver>,
+        f: F,
+        f_sens: FS,
+        t0: Realtype,
+        y0: &[Realtype],
+        y_s0: &[&[Realtype]],
+        rtol: Realtype,
+        atol: AbsToleranceDyn,
+        atol_sens: SensiAbsToleranceDyn,
+        user_data: UserData,
+    ) -> Result<Self> {
+        if y_s0.is_empty() {
+            return Err(Error::IllegalInput {
+                func_id: "CVodeSensInit",
+                reason: "there must be at least one sensitivity",
+            });
+        }
+        check_sens_size(y_s0, y0.len(), "CVodeSensInit")?;
+        atol_sens.check_size(y0.len(), y_s0.len())?;
+        let sensi = SensitivitiesDyn {
+            fs: f_sens,
+            y_s0: y_s0
+                .iter()
+                .map(|arr| NVectorSerialHeapAllocatedDyn::new_from(arr))
+                .collect(),
+            atol_sens,
+            sensi_out_buffer: y_s0
+                .iter()
+                .map(|_| NVectorSerialHeapAllocatedDyn::new(y0.len()))
+                .collect(),
+        };
+        let mut res = Self::create(
+            method,
+            solver.into(),
+            f,
+            t0,
+            y0,
+            rtol,
+            atol,
+            user_data,
+            sensi,
+        )?;
+        {
+            let fn_ptr =
+                wrap_f_sens::<UserData, F, FS> as extern "C" fn(_, _, _, _, _, _, _, _, _) -> _;
+            res.core.init_sens(
+                res.n_sensi(),
+                Some(unsafe { std::mem::transmute(fn_ptr) }),
+                res.user_data.sensi.y_s0.as_ptr() as _,
+                unsafe { res.y0.as_raw() },
+            )?;
+        }
+        res.core
+            .capture(|| res.user_data.sensi.atol_sens.set(res.core.mem, rtol))?;
+        Ok(res)
+    }
+
+    /// Returns the number of sensitivities.
+    pub fn n_sensi(&self) -> usize {
+        self.user_data.sensi.y_s0.len()
+    }
+
+    /// Reinitializes the solver with a new initial state and new initial
+    /// sensitivities, keeping all the allocations and options of the solver.
+    pub fn reinit(&mut self, t0: Realtype, y0: &[Realtype], y_s0: &[&[Realtype]]) -> Result<()> {
+        self.check_reinit_size(y0)?;
+        if y_s0.len() != self.n_sensi() {
+            return Err(Error::IllegalInput {
+                func_id: "CVodeSensReInit",
+                reason: "the number of initial sensitivities has changed",
+            });
+        }
+        check_sens_size(y_s0, self.len(), "CVodeSensReInit")?;
+        self.y0.as_slice_mut().copy_from_slice(y0);
+        let sensi = unsafe { &mut self.user_data.as_mut().get_unchecked_mut().sensi };
+        for (v, y_s0_i) in sensi.y_s0.iter_mut().zip(y_s0) {
+            v.as_slice_mut().copy_from_slice(y_s0_i);
+        }
+        self.core.reinit(t0, unsafe { self.y0.as_raw() })?;
+        self.core.reinit_sens(sensi.y_s0.as_ptr() as _)
+    }
+
+    forward_to_core!(sens);
+
+    /// Returns the `k`-th derivative of the interpolated sensitivities at
+    /// time `t`, with the same requirements as [`Self::dky`].
+    pub fn sens_dky(&self, t: Realtype, k: usize) -> Result<Vec<Vec<Realtype>>> {
+        let mut dky: Vec<_> = (0..self.n_sensi())
+            .map(|_| NVectorSerialHeapAllocatedDyn::new(self.len()))
+            .collect();
+        self.core.sens_dky(t, k, dky.as_mut_ptr() as _)?;
+        Ok(dky.iter().map(|v| v.as_slice().to_vec()).collect())
+    }
+
+    /// Returns the `k`-th derivative of the interpolated sensitivity with
+    /// respect to parameter `i` at time `t`, with the same requirements as
+    /// [`Self::dky`].
+    pub fn sens_dky1(&self, t: Realtype, k: usize, i: usize) -> Result<Vec<Realtype>> {
+        let dky = NVectorSerialHeapAllocatedDyn::new(self.len());
+        self.core
+            .sens_dky1(t, k, i, self.n_sensi(), unsafe { dky.as_raw() })?;
+        Ok(dky.as_slice().to_vec())
+    }
+
+    /// Takes a step according to `step_kind` (see [`StepKind`]).
+    ///
+    /// Returns a tuple `(t_out,&y(t_out),[&dy_dp(tout)],outcome)` where `t_out` is the time
+    /// reached by the solver as dictated by `step_kind`, `y(t_out)` is a slice of
+    /// the state variables at that time, the i-th `dy_dp(tout)` is a slice of the
+    /// sensitivities of all variables with respect to parameter i, and `outcome`
+    /// tells how the step ended.
+    ///
+    /// The failures of the functions called by cvode are reported as
+    /// described in the [crate documentation](crate#functions-called-by-cvode).
+    #[allow(clippy::type_complexity)]
+    pub fn step(
+        &mut self,
+        tout: Realtype,
+        step_kind: StepKind,
+    ) -> Result<(Realtype, &[Realtype], Vec<&[Realtype]>, StepOutcome)> {
+        let (tret, outcome) = self.step_state(tout, step_kind)?;
+        let sensi = unsafe { &mut self.user_data.as_mut().get_unchecked_mut().sensi };
+        self.core
+            .get_sens(sensi.sensi_out_buffer.as_mut_ptr() as _)?;
+        let sensi = self
+            .user_data
+            .sensi
+            .sensi_out_buffer
+            .iter()
+            .map(|v| v.as_slice())
+            .collect();
+        Ok((tret, self.y0.as_slice(), sensi, outcome))
+    }
+}
+
+/// Checks that all the sensitivities in `y_s` have length `size`.
+fn check_sens_size(y_s: &[&[Realtype]], size: usize, func_id: &'static str) -> Result<()> {
+    if y_s.iter().any(|y_s_i| y_s_i.len() != size) {
+        return Err(Error::IllegalInput {
+            func_id,
+            reason: "the initial sensitivities and the state have different lengths",
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::RhsResult;
+
+    use super::*;
+
+    fn f(_t: super::Realtype, y: &[Realtype], ydot: &mut [Realtype], _data: &()) -> RhsResult {
+        ydot[0] = y[1];
+        ydot[1] = -y[0];
+        RhsResult::Ok
+    }
+
+    fn fs(
+        _t: super::Realtype,
+        _y: &[Realtype],
+        _ydot: &[Realtype],
+        _ys: &[&[Realtype]],
+        ysdot: &mut [&mut [Realtype]],
+        _data: &(),
+    ) -> RhsResult {
+        for ysdot_i in ysdot {
+            ysdot_i.iter_mut().for_each(|x| *x = 0.);
+        }
+        RhsResult::Ok
+    }
+
+    #[test]
+    fn create() {
+        let y0 = [0., 1.];
+        let y_s0: [&[Realtype]; 3] = [&[1., 0.], &[0., 1.], &[0., 0.]];
+        let mut solver = Solver::new(
+            LinearMultistepMethod::Adams,
+            f,
+            fs,
+            0.,
+            &y0,
+            &y_s0,
+            1e-6,
+            AbsToleranceDyn::scalar(1e-6),
+            SensiAbsToleranceDyn::scalar(&[1e-6; 3]),
+            (),
+        )
+        .unwrap();
+        assert_eq!(solver.n_sensi(), 3);
+        let (_tret, y, y_s, _outcome) = solver.step(1., StepKind::Normal).unwrap();
+        assert!((y[0] - 1f64.sin()).abs() < 1e-4);
+        assert_eq!(y_s, y_s0);
+        assert_eq!(solver.sens_dky1(1., 0, 1).unwrap(), [0., 1.]);
+        assert!(solver.reinit(0., &y0, &y_s0[..2]).is_err());
+        assert!(Solver::new(
+            LinearMultistepMethod::Adams,
+            f,
+            fs,
+            0.,
+            &y0,
+            &y_s0,
+            1e-6,
+            AbsToleranceDyn::scalar(1e-6),
+            SensiAbsToleranceDyn::scalar(&[1e-6; 2]),
+            (),
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,230 @@
+use std::{ffi::CStr, fmt, os::raw::c_int};
+
+use crate::{sys, BoxedError, ErrorMessage, Realtype};
+
+/// The error type for this crate
+#[derive(Debug)]
+pub enum Error {
+    NullPointerError {
+        func_id: &'static str,
+    },
+    ErrorCode {
+        func_id: &'static str,
+        flag: c_int,
+        /// The last error message issued by sundials before the error, if
+        /// any.
+        message: Option<ErrorMessage>,
+    },
+    IllegalInput {
+        func_id: &'static str,
+        reason: &'static str,
+    },
+    /// The integration failed during a step.
+    StepFailed {
+        /// The time reached by the solver when the failure happened.
+        t: Realtype,
+        failure: CvodeFailure,
+        /// The error message issued by sundials along with the failure, if
+        /// any.
+        message: Option<ErrorMessage>,
+    },
+    /// A function called by cvode failed with [`RhsResult::Failed`](crate::RhsResult::Failed).
+    Rhs {
+        /// The time at which the function was called.
+        t: Realtype,
+        source: BoxedError,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            Error::NullPointerError { func_id } => {
+                return write!(f, "{} returned a null pointer", func_id)
+            }
+            Error::IllegalInput { func_id, reason } => {
+                return write!(f, "illegal input to {}: {}", func_id, reason)
+            }
+            Error::Rhs { t, source } => {
+                return write!(
+                    f,
+                    "a function called by cvode failed at t = {}: {}",
+                    t, source
+                )
+            }
+            Error::ErrorCode {
+                func_id,
+                flag,
+                message,
+            } => {
+                write!(f, "{} failed with flag {}", func_id, flag)?;
+                message
+            }
+            Error::StepFailed {
+                t,
+                failure,
+                message,
+            } => {
+                write!(f, "the integration failed at t = {} with {}", t, failure)?;
+                message
+            }
+        };
+        match message {
+            Some(message) => write!(f, ": {}", message.message),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Rhs { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// The reason of a failure of cvode during a step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CvodeFailure {
+    /// The maximum number of steps was taken before reaching the output time,
+    /// see [`Limits::max_num_steps`](crate::Limits::max_num_steps).
+    TooMuchWork,
+    /// The required accuracy could not be reached.
+    TooMuchAcc,
+    /// The error test failed repeatedly or with the minimum step size.
+    ErrFailure,
+    /// The nonlinear solver failed to converge repeatedly or with the
+    /// minimum step size.
+    ConvFailure,
+    /// The initialization of the linear solver failed.
+    LinitFail,
+    /// The setup of the linear solver failed in an unrecoverable manner.
+    LsetupFail,
+    /// The linear solver failed in an unrecoverable manner.
+    LsolveFail,
+    /// The right-hand side failed in an unrecoverable manner.
+    RhsFuncFail,
+    /// The right-hand side failed in a recoverable manner at the first call.
+    FirstRhsFuncErr,
+    /// The right-hand side failed repeatedly in a recoverable manner.
+    RepeatedRhsFuncErr,
+    /// The right-hand side failed in a recoverable manner, but cvode could
+    /// not recover.
+    UnrecRhsFuncErr,
+    /// The root function failed.
+    RtFuncFail,
+    /// The initialization of the nonlinear solver failed.
+    NlsInitFail,
+    /// The setup of the nonlinear solver failed.
+    NlsSetupFail,
+    /// The inequality constraints could not be met.
+    ConstrFail,
+    /// The nonlinear solver failed in an unrecoverable manner.
+    NlsFail,
+    /// A memory allocation failed.
+    MemFail,
+    /// An input of the step was illegal.
+    IllInput,
+    /// The output time is too close to the initial time.
+    TooClose,
+    /// A vector operation failed.
+    VectorOpErr,
+    /// The sensitivities right-hand side failed in an unrecoverable manner.
+    SrhsFuncFail,
+    /// The sensitivities right-hand side failed in a recoverable manner at
+    /// the first call.
+    FirstSrhsFuncErr,
+    /// The sensitivities right-hand side failed repeatedly in a recoverable
+    /// manner.
+    RepeatedSrhsFuncErr,
+    /// The sensitivities right-hand side failed in a recoverable manner, but
+    /// cvode could not recover.
+    UnrecSrhsFuncErr,
+    /// Any other negative flag.
+    Other(c_int),
+}
+
+impl CvodeFailure {
+    /// Converts a negative flag returned by `CVode`.
+    pub(crate) fn from_flag(flag: c_int) -> Self {
+        match flag {
+            sundials_sys::CV_TOO_MUCH_WORK => CvodeFailure::TooMuchWork,
+            sundials_sys::CV_TOO_MUCH_ACC => CvodeFailure::TooMuchAcc,
+            sundials_sys::CV_ERR_FAILURE => CvodeFailure::ErrFailure,
+            sundials_sys::CV_CONV_FAILURE => CvodeFailure::ConvFailure,
+            sundials_sys::CV_LINIT_FAIL => CvodeFailure::LinitFail,
+            sundials_sys::CV_LSETUP_FAIL => CvodeFailure::LsetupFail,
+            sundials_sys::CV_LSOLVE_FAIL => CvodeFailure::LsolveFail,
+            sundials_sys::CV_RHSFUNC_FAIL => CvodeFailure::RhsFuncFail,
+            sundials_sys::CV_FIRST_RHSFUNC_ERR => CvodeFailure::FirstRhsFuncErr,
+            sundials_sys::CV_REPTD_RHSFUNC_ERR => CvodeFailure::RepeatedRhsFuncErr,
+            sundials_sys::CV_UNREC_RHSFUNC_ERR => CvodeFailure::UnrecRhsFuncErr,
+            sundials_sys::CV_RTFUNC_FAIL => CvodeFailure::RtFuncFail,
+            sundials_sys::CV_NLS_INIT_FAIL => CvodeFailure::NlsInitFail,
+            sundials_sys::CV_NLS_SETUP_FAIL => CvodeFailure::NlsSetupFail,
+            sundials_sys::CV_CONSTR_FAIL => CvodeFailure::ConstrFail,
+            sundials_sys::CV_NLS_FAIL => CvodeFailure::NlsFail,
+            sundials_sys::CV_MEM_FAIL => CvodeFailure::MemFail,
+            sundials_sys::CV_ILL_INPUT => CvodeFailure::IllInput,
+            sundials_sys::CV_TOO_CLOSE => CvodeFailure::TooClose,
+            sundials_sys::CV_VECTOROP_ERR => CvodeFailure::VectorOpErr,
+            sundials_sys::CV_SRHSFUNC_FAIL => CvodeFailure::SrhsFuncFail,
+            sundials_sys::CV_FIRST_SRHSFUNC_ERR => CvodeFailure::FirstSrhsFuncErr,
+            sundials_sys::CV_REPTD_SRHSFUNC_ERR => CvodeFailure::RepeatedSrhsFuncErr,
+            sundials_sys::CV_UNREC_SRHSFUNC_ERR => CvodeFailure::UnrecSrhsFuncErr,
+            flag => CvodeFailure::Other(flag),
+        }
+    }
+
+    /// Returns the flag returned by `CVode`.
+    pub fn flag(self) -> c_int {
+        match self {
+            CvodeFailure::TooMuchWork => sundials_sys::CV_TOO_MUCH_WORK,
+            CvodeFailure::TooMuchAcc => sundials_sys::CV_TOO_MUCH_ACC,
+            CvodeFailure::ErrFailure => sundials_sys::CV_ERR_FAILURE,
+            CvodeFailure::ConvFailure => sundials_sys::CV_CONV_FAILURE,
+            CvodeFailure::LinitFail => sundials_sys::CV_LINIT_FAIL,
+            CvodeFailure::LsetupFail => sundials_sys::CV_LSETUP_FAIL,
+            CvodeFailure::LsolveFail => sundials_sys::CV_LSOLVE_FAIL,
+            CvodeFailure::RhsFuncFail => sundials_sys::CV_RHSFUNC_FAIL,
+            CvodeFailure::FirstRhsFuncErr => sundials_sys::CV_FIRST_RHSFUNC_ERR,
+            CvodeFailure::RepeatedRhsFuncErr => sundials_sys::CV_REPTD_RHSFUNC_ERR,
+            CvodeFailure::UnrecRhsFuncErr => sundials_sys::CV_UNREC_RHSFUNC_ERR,
+            CvodeFailure::RtFuncFail => sundials_sys::CV_RTFUNC_FAIL,
+            CvodeFailure::NlsInitFail => sundials_sys::CV_NLS_INIT_FAIL,
+            CvodeFailure::NlsSetupFail => sundials_sys::CV_NLS_SETUP_FAIL,
+            CvodeFailure::ConstrFail => sundials_sys::CV_CONSTR_FAIL,
+            CvodeFailure::NlsFail => sundials_sys::CV_NLS_FAIL,
+            CvodeFailure::MemFail => sundials_sys::CV_MEM_FAIL,
+            CvodeFailure::IllInput => sundials_sys::CV_ILL_INPUT,
+            CvodeFailure::TooClose => sundials_sys::CV_TOO_CLOSE,
+            CvodeFailure::VectorOpErr => sundials_sys::CV_VECTOROP_ERR,
+            CvodeFailure::SrhsFuncFail => sundials_sys::CV_SRHSFUNC_FAIL,
+            CvodeFailure::FirstSrhsFuncErr => sundials_sys::CV_FIRST_SRHSFUNC_ERR,
+            CvodeFailure::RepeatedSrhsFuncErr => sundials_sys::CV_REPTD_SRHSFUNC_ERR,
+            CvodeFailure::UnrecSrhsFuncErr => sundials_sys::CV_UNREC_SRHSFUNC_ERR,
+            CvodeFailure::Other(flag) => flag,
+        }
+    }
+
+    /// Returns the name of the flag given by cvode, e.g. `"CV_TOO_MUCH_WORK"`.
+    pub fn name(self) -> String {
+        let ptr = unsafe { sundials_sys::CVodeGetReturnFlagName(self.flag().into()) };
+        if ptr.is_null() {
+            return format!("flag {}", self.flag());
+        }
+        let name = unsafe { CStr::from_ptr(ptr) }
+            .to_string_lossy()
+            .into_owned();
+        unsafe { sys::free(ptr as _) };
+        name
+    }
+}
+
+impl fmt::Display for CvodeFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name())
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    cell::RefCell,
+    ffi::CStr,
+    os::raw::{c_char, c_int},
+    panic::{self, AssertUnwindSafe},
+};
+
+use crate::{check_flag_is_succes, CvodeMemoryBlockNonNullPtr, Error, Result};
+
+/// A message issued by sundials along with an error or a warning.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorMessage {
+    /// The sundials error code, negative for errors and positive for warnings.
+    pub error_code: c_int,
+    /// The sundials module which issued the message, e.g. `"CVODES"`.
+    pub module: String,
+    /// The sundials function which issued the message.
+    pub function: String,
+    /// The text of the message.
+    pub message: String,
+}
+
+/// Captures the error messages of a cvode memory block in place of printing
+/// them on stderr.
+///
+/// The last error message is kept to be attached to the next
+/// [`Error::ErrorCode`] or [`Error::StepFailed`]. With the `log` feature,
+/// warnings are forwarded to the `log` crate, otherwise they are printed on
+/// stderr as cvode does.
+pub(crate) struct ErrorHandler {
+    last_error: Box<RefCell<Option<ErrorMessage>>>,
+}
+
+extern "C" fn wrap_err_handler(
+    error_code: c_int,
+    module: *const c_char,
+    function: *const c_char,
+    msg: *const c_char,
+    eh_data: *const RefCell<Option<ErrorMessage>>,
+) {
+    // A panic, e.g. of the logger, must not unwind into cvode, and cannot be
+    // resumed from here, so it is dropped.
+    let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+        let to_string = |s: *const c_char| {
+            if s.is_null() {
+                String::new()
+            } else {
+                unsafe { CStr::from_ptr(s) }.to_string_lossy().into_owned()
+            }
+        };
+        let message = ErrorMessage {
+            error_code,
+            module: to_string(module),
+            function: to_string(function),
+            message: to_string(msg),
+        };
+        if error_code < 0 {
+            *unsafe { &*eh_data }.borrow_mut() = Some(message);
+        } else {
+            #[cfg(feature = "log")]
+            log::warn!(
+                "[{} WARNING] {}: {}",
+                message.module,
+                message.function,
+                message.message
+            );
+            // Without the `log` feature, warnings are printed as cvode does.
+            #[cfg(not(feature = "log"))]
+            {
+                use std::io::Write;
+                let _ = write!(
+                    std::io::stderr(),
+                    "\n[{} WARNING]  {}\n  {}\n\n",
+                    message.module,
+                    message.function,
+                    message.message
+                );
+            }
+        }
+    }));
+}
+
+impl ErrorHandler {
+    pub(crate) fn new() -> Self {
+        ErrorHandler {
+            last_error: Box::new(RefCell::new(None)),
+        }
+    }
+
+    /// Installs the handler on `mem`, which must not outlive it.
+    pub(crate) fn install(&self, mem: CvodeMemoryBlockNonNullPtr) -> Result<()> {
+        let fn_ptr = wrap_err_handler as extern "C" fn(_, _, _, _, _);
+        let flag = unsafe {
+            sundials_sys::CVodeSetErrHandlerFn(
+                mem.as_raw(),
+                Some(std::mem::transmute(fn_ptr)),
+                &*self.last_error as *const _ as _,
+            )
+        };
+        check_flag_is_succes(flag, "CVodeSetErrHandlerFn")
+    }
+
+    /// Calls `f`, attaching to the [`Error::ErrorCode`] or
+    /// [`Error::StepFailed`] it may return the message issued meanwhile.
+    pub(crate) fn capture<T>(&self, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        self.last_error.borrow_mut().take();
+        f().map_err(|e| match e {
+            Error::ErrorCode {
+                func_id,
+                flag,
+                message: None,
+            } => Error::ErrorCode {
+                func_id,
+                flag,
+                message: self.last_error.borrow_mut().take(),
+            },
+            Error::StepFailed {
+                t,
+                failure,
+                message: None,
+            } => Error::StepFailed {
+                t,
+                failure,
+                message: self.last_error.borrow_mut().take(),
+            },
+            e => e,
+        })
+    }
+}
@@ -1,11 +1,40 @@
 //! A wrapper around cvode and cvodes from the sundials tool suite.
 //!
 //! Users should be mostly interested in [`SolverSensi`] and [`SolverNoSensi`].
+//! When the size of the problem is only known at run time, [`SolverSensiDyn`]
+//! and [`SolverNoSensiDyn`] take slices instead of arrays. [`SolverAdjoint`]
+//! computes sensitivities by adjoint sensitivity analysis, which is cheaper
+//! than [`SolverSensi`] when there are many parameters.
 //!
 //! # Building sundials
 //!
 //! To build sundials, activate the `sundials-sys/build_libraries` feature.
 //!
+//! # Sparse linear solver
+//!
+//! The sparse direct linear solver (see [`LinearSolver`]) relies on KLU, and is
+//! enabled by the `klu` feature. It requires a sundials library built with KLU
+//! support, which `sundials-sys/build_libraries` does not provide.
+//!
+//! The methods shared by [`SolverSensi`] and [`SolverNoSensi`] are those of
+//! [`GenericSolver`], and the ones shared by [`SolverSensiDyn`] and
+//! [`SolverNoSensiDyn`] are those of [`GenericSolverDyn`]. The functions
+//! called by cvode other than the right-hand sides (Jacobian, preconditioner,
+//! root functions...) and the constraints are not available on the latter.
+//!
+//! # Functions called by cvode
+//!
+//! A panic of one of the functions called by cvode during a step is resumed
+//! once cvode has returned, and a [`RhsResult::Failed`] is returned as
+//! [`Error::Rhs`].
+//!
+//! # Error messages
+//!
+//! The error messages of sundials are not printed, but attached to the
+//! [`Error::ErrorCode`] they come with. Its warnings are printed on stderr,
+//! or forwarded to the [`log`](https://docs.rs/log) crate with the `log`
+//! feature.
+//!
 //! # Examples
 //!
 //! ## Oscillator
@@ -37,7 +66,7 @@
 //! let ts: Vec<_> = (1..100).collect();
 //! println!("0,{},{}", y0[0], y0[1]);
 //! for &t in &ts {
-//!     let (_tret, &[x, xdot]) = solver.step(t as _, StepKind::Normal).unwrap();
+//!     let (_tret, &[x, xdot], _outcome) = solver.step(t as _, StepKind::Normal).unwrap();
 //!     println!("{},{},{}", t, x, xdot);
 //! }
 //! ```
@@ -95,8 +124,12 @@
 //! let ts: Vec<_> = (1..100).collect();
 //! println!("0,{},{}", y0[0], y0[1]);
 //! for &t in &ts {
-//!     let (_tret, &[x, xdot], [&[dy0_dy00, dy1_dy00], &[dy0_dy01, dy1_dy01], &[dy0_dk, dy1_dk]]) =
-//!         solver.step(t as _, StepKind::Normal).unwrap();
+//!     let (
+//!         _tret,
+//!         &[x, xdot],
+//!         [&[dy0_dy00, dy1_dy00], &[dy0_dy01, dy1_dy01], &[dy0_dk, dy1_dk]],
+//!         _outcome,
+//!     ) = solver.step(t as _, StepKind::Normal).unwrap();
 //!     println!(
 //!         "{},{},{},{},{},{},{},{},{}",
 //!         t, x, xdot, dy0_dy00, dy1_dy00, dy0_dy01, dy1_dy01, dy0_dk, dy1_dk
@@ -108,22 +141,71 @@ use std::{ffi::c_void, os::raw::c_int, ptr::NonNull};
 use sundials_sys::realtype;
 
 mod nvector;
-pub use nvector::{NVectorSerial, NVectorSerialHeapAllocated};
+pub use nvector::{
+    NVectorSerial, NVectorSerialDyn, NVectorSerialHeapAllocated, NVectorSerialHeapAllocatedDyn,
+};
+
+mod sunmatrix;
+pub use sunmatrix::{BandMatrixView, DenseMatrixView};
+#[cfg(feature = "klu")]
+pub use sunmatrix::{SparseFormat, SparseMatrixView};
+
+mod linear_solver;
+pub use linear_solver::{
+    BuiltinPreconditioner, GramSchmidt, KrylovMethod, LinearSolver, PreconditionerSide,
+};
+
+mod limits;
+pub use limits::Limits;
+
+mod nonlinear_solver;
+pub use nonlinear_solver::NonlinearSolver;
+
+mod stats;
+pub use stats::{DiagStats, IntegratorStats, LinearSolverStats, PreconditionerStats, SensStats};
+
+mod roots;
+pub use roots::{RootCrossing, RootDirection};
+
+mod constraints;
+pub use constraints::Constraint;
+
+mod callback_failure;
+mod callbacks;
+
+mod error;
+pub use error::{CvodeFailure, Error};
+
+mod error_handler;
+pub use error_handler::ErrorMessage;
 
 mod cvode;
+mod cvode_adjoint;
+mod cvode_dyn;
 mod cvode_sens;
+mod cvode_sens_dyn;
 
-pub use cvode::Solver as SolverNoSensi;
-pub use cvode_sens::Solver as SolverSensi;
+mod solver_core;
+mod sys;
+
+pub use cvode::{GenericSolver, Solver as SolverNoSensi};
+pub use cvode_adjoint::{AdjointInterpolation, BackwardProblem, Solver as SolverAdjoint};
+pub use cvode_dyn::{GenericSolverDyn, Solver as SolverNoSensiDyn};
+pub use cvode_sens::{Sensitivities, Solver as SolverSensi};
+pub use cvode_sens_dyn::{SensitivitiesDyn, Solver as SolverSensiDyn};
 
 /// The floatting-point type sundials was compiled with
 pub type Realtype = realtype;
 
+/// The integer type sundials uses for indices
+pub type Indextype = sundials_sys::sunindextype;
+
 #[repr(i32)]
 #[derive(Debug)]
 /// An integration method.
 pub enum LinearMultistepMethod {
-    /// Recomended for non-stiff problems.
+    /// Recomended for non-stiff problems, along with
+    /// [`NonlinearSolver::FixedPoint`].
     Adams = sundials_sys::CV_ADAMS,
     /// Recommended for stiff problems.
     Bdf = sundials_sys::CV_BDF,
@@ -148,27 +230,104 @@ pub enum RhsResult {
     RecoverableError(u8),
     /// Indicatest hat there was a non recoverable error
     NonRecoverableError(u8),
+    /// Indicates that there was a non recoverable error, which is returned by
+    /// `step` as [`Error::Rhs`]
+    Failed(BoxedError),
+}
+
+impl RhsResult {
+    fn to_c_int(&self) -> c_int {
+        match *self {
+            RhsResult::Ok => 0,
+            RhsResult::RecoverableError(e) => e as c_int,
+            RhsResult::NonRecoverableError(e) => -(e as c_int),
+            RhsResult::Failed(_) => -1,
+        }
+    }
 }
 
+/// An error returned by a function called by cvode, see [`RhsResult::Failed`].
+pub type BoxedError = Box<dyn std::error::Error + Send + Sync>;
+
 /// Type of integration step
-#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum StepKind {
     /// The `NORMAL`option causes the solver to take internal steps
     /// until it has reached or just passed the user-specified time.
     /// The solver then interpolates in order to return an approximate
     /// value of y at the desired time.
-    Normal = sundials_sys::CV_NORMAL,
+    Normal,
     /// The `CV_ONE_STEP` option tells the solver to take just one
     /// internal step and then return thesolution at the point reached
     /// by that step.
-    OneStep = sundials_sys::CV_ONE_STEP,
+    OneStep,
+    /// Like [`StepKind::Normal`], but fails if no stop time has been set
+    /// with the `set_stop_time` method of the solvers. Note that the stop
+    /// time is honoured by all the step kinds.
+    NormalTstop,
+    /// Like [`StepKind::OneStep`], but fails if no stop time has been set
+    /// with the `set_stop_time` method of the solvers. Note that the stop
+    /// time is honoured by all the step kinds.
+    OneStepTstop,
 }
 
-/// The error type for this crate
-#[derive(Debug)]
-pub enum Error {
-    NullPointerError { func_id: &'static str },
-    ErrorCode { func_id: &'static str, flag: c_int },
+impl StepKind {
+    /// The `itask` argument of `CVode`.
+    fn itask(self) -> c_int {
+        match self {
+            StepKind::Normal | StepKind::NormalTstop => sundials_sys::CV_NORMAL,
+            StepKind::OneStep | StepKind::OneStepTstop => sundials_sys::CV_ONE_STEP,
+        }
+    }
+
+    fn stops_at_stop_time(self) -> bool {
+        matches!(self, StepKind::NormalTstop | StepKind::OneStepTstop)
+    }
+}
+
+/// How a step ended, returned along with the state by the `step` method of
+/// the solvers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// The step ended as dictated by its [`StepKind`].
+    Reached,
+    /// The step ended at a root of the root function. The vector contains,
+    /// for each component of the root function, how it crossed zero if
+    /// it did.
+    RootFound(Vec<Option<RootCrossing>>),
+    /// The step ended at the stop time.
+    StopTimeReached,
+    /// The step succeeded, but cvode issued a warning with the given flag.
+    Warning(c_int),
+}
+
+impl StepOutcome {
+    /// Interprets the flag returned by `CVode`, `num_roots` being the number of
+    /// components of the root function. Only negative flags are errors.
+    fn from_flag(
+        flag: c_int,
+        mem: CvodeMemoryBlockNonNullPtr,
+        num_roots: usize,
+    ) -> Result<StepOutcome> {
+        match flag {
+            sundials_sys::CV_SUCCESS => Ok(StepOutcome::Reached),
+            sundials_sys::CV_TSTOP_RETURN => Ok(StepOutcome::StopTimeReached),
+            sundials_sys::CV_ROOT_RETURN => {
+                Ok(StepOutcome::RootFound(roots::root_info(mem, num_roots)?))
+            }
+            flag if flag > 0 => Ok(StepOutcome::Warning(flag)),
+            flag => {
+                let mut t = 0.;
+                let res = unsafe { sundials_sys::CVodeGetCurrentTime(mem.as_raw(), &mut t) };
+                check_flag_is_succes(res, "CVodeGetCurrentTime")?;
+                Err(Error::StepFailed {
+                    t,
+                    failure: CvodeFailure::from_flag(flag),
+                    message: None,
+                })
+            }
+        }
+    }
 }
 
 /// An enum representing the choice between a scalar or vector absolute tolerance
@@ -186,6 +345,69 @@ impl<const SIZE: usize> AbsTolerance<SIZE> {
         let atol = NVectorSerialHeapAllocated::new_from(atol);
         AbsTolerance::Vector(atol)
     }
+
+    /// Sets the tolerances in cvode, along with the relative tolerance `rtol`.
+    fn set(&self, mem: CvodeMemoryBlockNonNullPtr, rtol: Realtype) -> Result<()> {
+        match self {
+            &AbsTolerance::Scalar(atol) => {
+                let flag = unsafe { sundials_sys::CVodeSStolerances(mem.as_raw(), rtol, atol) };
+                check_flag_is_succes(flag, "CVodeSStolerances")
+            }
+            AbsTolerance::Vector(atol) => {
+                let flag =
+                    unsafe { sundials_sys::CVodeSVtolerances(mem.as_raw(), rtol, atol.as_raw()) };
+                check_flag_is_succes(flag, "CVodeSVtolerances")
+            }
+        }
+    }
+
+    /// Sets the tolerances of the backward problem `which` of the adjoint
+    /// memory block `mem`, along with the relative tolerance `rtol`.
+    fn set_backward(
+        &self,
+        mem: CvodeMemoryBlockNonNullPtr,
+        which: c_int,
+        rtol: Realtype,
+    ) -> Result<()> {
+        match self {
+            &AbsTolerance::Scalar(atol) => {
+                let flag =
+                    unsafe { sundials_sys::CVodeSStolerancesB(mem.as_raw(), which, rtol, atol) };
+                check_flag_is_succes(flag, "CVodeSStolerancesB")
+            }
+            AbsTolerance::Vector(atol) => {
+                let flag = unsafe {
+                    sundials_sys::CVodeSVtolerancesB(mem.as_raw(), which, rtol, atol.as_raw())
+                };
+                check_flag_is_succes(flag, "CVodeSVtolerancesB")
+            }
+        }
+    }
+
+    /// Sets the tolerances of the quadrature of the backward problem `which`
+    /// of the adjoint memory block `mem`, along with the relative tolerance
+    /// `rtol`.
+    fn set_backward_quad(
+        &self,
+        mem: CvodeMemoryBlockNonNullPtr,
+        which: c_int,
+        rtol: Realtype,
+    ) -> Result<()> {
+        match self {
+            &AbsTolerance::Scalar(atol) => {
+                let flag = unsafe {
+                    sundials_sys::CVodeQuadSStolerancesB(mem.as_raw(), which, rtol, atol)
+                };
+                check_flag_is_succes(flag, "CVodeQuadSStolerancesB")
+            }
+            AbsTolerance::Vector(atol) => {
+                let flag = unsafe {
+                    sundials_sys::CVodeQuadSVtolerancesB(mem.as_raw(), which, rtol, atol.as_raw())
+                };
+                check_flag_is_succes(flag, "CVodeQuadSVtolerancesB")
+            }
+        }
+    }
 }
 
 /// An enum representing the choice between scalars or vectors absolute tolerances
@@ -209,6 +431,135 @@ impl<const SIZE: usize, const N_SENSI: usize> SensiAbsTolerance<SIZE, N_SENSI> {
             .unwrap(),
         )
     }
+
+    /// Sets the tolerances of the sensitivities in cvode, along with the
+    /// relative tolerance `rtol`.
+    fn set(&self, mem: CvodeMemoryBlockNonNullPtr, rtol: Realtype) -> Result<()> {
+        match self {
+            SensiAbsTolerance::Scalar(atol) => {
+                let flag = unsafe {
+                    sundials_sys::CVodeSensSStolerances(mem.as_raw(), rtol, atol.as_ptr() as _)
+                };
+                check_flag_is_succes(flag, "CVodeSensSStolerances")
+            }
+            SensiAbsTolerance::Vector(atol) => {
+                let flag = unsafe {
+                    sundials_sys::CVodeSensSVtolerances(mem.as_raw(), rtol, atol.as_ptr() as _)
+                };
+                check_flag_is_succes(flag, "CVodeSensSVtolerances")
+            }
+        }
+    }
+}
+
+/// An enum representing the choice between a scalar or vector absolute tolerance,
+/// for the solvers whose size is only known at run time.
+pub enum AbsToleranceDyn {
+    Scalar(Realtype),
+    Vector(NVectorSerialHeapAllocatedDyn),
+}
+
+impl AbsToleranceDyn {
+    pub fn scalar(atol: Realtype) -> Self {
+        AbsToleranceDyn::Scalar(atol)
+    }
+
+    pub fn vector(atol: &[Realtype]) -> Self {
+        let atol = NVectorSerialHeapAllocatedDyn::new_from(atol);
+        AbsToleranceDyn::Vector(atol)
+    }
+
+    /// Checks that the tolerances fit a problem of size `size`.
+    fn check_size(&self, size: usize) -> Result<()> {
+        match self {
+            AbsToleranceDyn::Vector(atol) if atol.len() != size => Err(Error::IllegalInput {
+                func_id: "CVodeSVtolerances",
+                reason: "the absolute tolerance and the state have different lengths",
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Sets the tolerances in cvode, along with the relative tolerance `rtol`.
+    fn set(&self, mem: CvodeMemoryBlockNonNullPtr, rtol: Realtype) -> Result<()> {
+        match self {
+            &AbsToleranceDyn::Scalar(atol) => {
+                let flag = unsafe { sundials_sys::CVodeSStolerances(mem.as_raw(), rtol, atol) };
+                check_flag_is_succes(flag, "CVodeSStolerances")
+            }
+            AbsToleranceDyn::Vector(atol) => {
+                let flag =
+                    unsafe { sundials_sys::CVodeSVtolerances(mem.as_raw(), rtol, atol.as_raw()) };
+                check_flag_is_succes(flag, "CVodeSVtolerances")
+            }
+        }
+    }
+}
+
+/// An enum representing the choice between scalars or vectors absolute tolerances
+/// for sensitivities, for the solvers whose size is only known at run time.
+pub enum SensiAbsToleranceDyn {
+    Scalar(Vec<Realtype>),
+    Vector(Vec<NVectorSerialHeapAllocatedDyn>),
+}
+
+impl SensiAbsToleranceDyn {
+    pub fn scalar(atol: &[Realtype]) -> Self {
+        SensiAbsToleranceDyn::Scalar(atol.to_vec())
+    }
+
+    pub fn vector(atol: &[&[Realtype]]) -> Self {
+        SensiAbsToleranceDyn::Vector(
+            atol.iter()
+                .map(|arr| NVectorSerialHeapAllocatedDyn::new_from(arr))
+                .collect(),
+        )
+    }
+
+    /// Checks that the tolerances fit `n_sensi` sensitivities of a problem
+    /// of size `size`.
+    fn check_size(&self, size: usize, n_sensi: usize) -> Result<()> {
+        match self {
+            SensiAbsToleranceDyn::Scalar(atol) if atol.len() != n_sensi => {
+                Err(Error::IllegalInput {
+                    func_id: "CVodeSensSStolerances",
+                    reason: "there must be one absolute tolerance per sensitivity",
+                })
+            }
+            SensiAbsToleranceDyn::Vector(atol) if atol.len() != n_sensi => {
+                Err(Error::IllegalInput {
+                    func_id: "CVodeSensSVtolerances",
+                    reason: "there must be one absolute tolerance per sensitivity",
+                })
+            }
+            SensiAbsToleranceDyn::Vector(atol) if atol.iter().any(|v| v.len() != size) => {
+                Err(Error::IllegalInput {
+                    func_id: "CVodeSensSVtolerances",
+                    reason: "the absolute tolerances and the state have different lengths",
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Sets the tolerances of the sensitivities in cvode, along with the
+    /// relative tolerance `rtol`.
+    fn set(&self, mem: CvodeMemoryBlockNonNullPtr, rtol: Realtype) -> Result<()> {
+        match self {
+            SensiAbsToleranceDyn::Scalar(atol) => {
+                let flag = unsafe {
+                    sundials_sys::CVodeSensSStolerances(mem.as_raw(), rtol, atol.as_ptr() as _)
+                };
+                check_flag_is_succes(flag, "CVodeSensSStolerances")
+            }
+            SensiAbsToleranceDyn::Vector(atol) => {
+                let flag = unsafe {
+                    sundials_sys::CVodeSensSVtolerances(mem.as_raw(), rtol, atol.as_ptr() as _)
+                };
+                check_flag_is_succes(flag, "CVodeSensSVtolerances")
+            }
+        }
+    }
 }
 
 /// A short-hand for `std::result::Result<T, crate::Error>`
@@ -222,7 +573,11 @@ fn check_flag_is_succes(flag: c_int, func_id: &'static str) -> Result<()> {
     if flag == sundials_sys::CV_SUCCESS {
         Ok(())
     } else {
-        Err(Error::ErrorCode { flag, func_id })
+        Err(Error::ErrorCode {
+            flag,
+            func_id,
+            message: None,
+        })
     }
 }
 
@@ -0,0 +1,219 @@
+use std::{
+    convert::TryFrom,
+    os::raw::{c_int, c_long},
+};
+
+use crate::{check_flag_is_succes, CvodeMemoryBlockNonNullPtr, Error, Realtype, Result};
+
+/// Optional limits of the integration, applied with the `set_limits` method
+/// of the solvers.
+///
+/// Limits which are not set keep their current value, which is the cvode
+/// default unless it has been changed before.
+///
+/// ```
+/// use cvode_wrap::Limits;
+///
+/// let limits = Limits::new().max_num_steps(10_000).max_step(0.1);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    max_num_steps: Option<usize>,
+    max_order: Option<usize>,
+    init_step: Option<Realtype>,
+    min_step: Option<Realtype>,
+    max_step: Option<Realtype>,
+    max_hnil_warns: Option<usize>,
+    max_err_test_fails: Option<usize>,
+    max_conv_fails: Option<usize>,
+}
+
+impl Limits {
+    /// Creates limits where nothing is set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maximum number of internal steps taken during a single call to `step`,
+    /// 500 by default.
+    pub fn max_num_steps(mut self, max_num_steps: usize) -> Self {
+        self.max_num_steps = Some(max_num_steps);
+        self
+    }
+
+    /// Maximum order of the linear multistep method, which cannot be
+    /// increased after the solver is created.
+    pub fn max_order(mut self, max_order: usize) -> Self {
+        self.max_order = Some(max_order);
+        self
+    }
+
+    /// Step size of the first step, `0.` meaning that it is estimated by
+    /// cvode.
+    pub fn init_step(mut self, init_step: Realtype) -> Self {
+        self.init_step = Some(init_step);
+        self
+    }
+
+    /// Lower bound on the absolute value of the step size, `0.` by default.
+    pub fn min_step(mut self, min_step: Realtype) -> Self {
+        self.min_step = Some(min_step);
+        self
+    }
+
+    /// Upper bound on the absolute value of the step size, unbounded by
+    /// default.
+    pub fn max_step(mut self, max_step: Realtype) -> Self {
+        self.max_step = Some(max_step);
+        self
+    }
+
+    /// Maximum number of warnings issued when `t + h == t` on the next step,
+    /// 10 by default.
+    pub fn max_hnil_warns(mut self, max_hnil_warns: usize) -> Self {
+        self.max_hnil_warns = Some(max_hnil_warns);
+        self
+    }
+
+    /// Maximum number of error test failures during one step, 7 by default.
+    pub fn max_err_test_fails(mut self, max_err_test_fails: usize) -> Self {
+        self.max_err_test_fails = Some(max_err_test_fails);
+        self
+    }
+
+    /// Maximum number of nonlinear solver convergence failures during one
+    /// step, 10 by default.
+    pub fn max_conv_fails(mut self, max_conv_fails: usize) -> Self {
+        self.max_conv_fails = Some(max_conv_fails);
+        self
+    }
+
+    /// Validates the limits, converting the integers to the types of
+    /// cvode.
+    fn validate(&self) -> Result<ValidLimits> {
+        if self.max_num_steps == Some(0) {
+            return Err(Error::IllegalInput {
+                func_id: "CVodeSetMaxNumSteps",
+                reason: "the maximum number of steps must be positive",
+            });
+        }
+        if self.max_order == Some(0) {
+            return Err(Error::IllegalInput {
+                func_id: "CVodeSetMaxOrd",
+                reason: "the maximum order must be positive",
+            });
+        }
+        if let Some(init_step) = self.init_step {
+            if init_step.is_nan() {
+                return Err(Error::IllegalInput {
+                    func_id: "CVodeSetInitStep",
+                    reason: "the initial step size must not be NaN",
+                });
+            }
+        }
+        if let Some(min_step) = self.min_step {
+            if min_step.is_nan() || min_step < 0. {
+                return Err(Error::IllegalInput {
+                    func_id: "CVodeSetMinStep",
+                    reason: "the minimum step size must be non-negative",
+                });
+            }
+        }
+        if let Some(max_step) = self.max_step {
+            if max_step.is_nan() || max_step <= 0. {
+                return Err(Error::IllegalInput {
+                    func_id: "CVodeSetMaxStep",
+                    reason: "the maximum step size must be positive",
+                });
+            }
+        }
+        if let (Some(min_step), Some(max_step)) = (self.min_step, self.max_step) {
+            if min_step > max_step {
+                return Err(Error::IllegalInput {
+                    func_id: "CVodeSetMinStep",
+                    reason: "the minimum step size must not exceed the maximum step size",
+                });
+            }
+        }
+        Ok(ValidLimits {
+            max_num_steps: checked_limit(self.max_num_steps, "CVodeSetMaxNumSteps")?,
+            max_order: checked_limit(self.max_order, "CVodeSetMaxOrd")?,
+            max_hnil_warns: checked_limit(self.max_hnil_warns, "CVodeSetMaxHnilWarns")?,
+            max_err_test_fails: checked_limit(self.max_err_test_fails, "CVodeSetMaxErrTestFails")?,
+            max_conv_fails: checked_limit(self.max_conv_fails, "CVodeSetMaxConvFails")?,
+        })
+    }
+
+    /// Validates the limits and sets those that are set in cvode.
+    ///
+    /// Nothing is set if the limits are invalid. The limits checked by cvode
+    /// against its current ones, the maximum order and the step sizes, are
+    /// set first, but the ones set before a failure are kept.
+    pub(crate) fn apply(&self, mem: CvodeMemoryBlockNonNullPtr) -> Result<()> {
+        let valid = self.validate()?;
+        let mem = mem.as_raw();
+        if let Some(max_order) = valid.max_order {
+            let flag = unsafe { sundials_sys::CVodeSetMaxOrd(mem, max_order) };
+            check_flag_is_succes(flag, "CVodeSetMaxOrd")?;
+        }
+        // cvode checks the new minimum against the current maximum and
+        // conversely, so the minimum is reset when both are changed.
+        if let (Some(_), Some(_)) = (self.min_step, self.max_step) {
+            let flag = unsafe { sundials_sys::CVodeSetMinStep(mem, 0.) };
+            check_flag_is_succes(flag, "CVodeSetMinStep")?;
+        }
+        if let Some(max_step) = self.max_step {
+            let flag = unsafe { sundials_sys::CVodeSetMaxStep(mem, max_step) };
+            check_flag_is_succes(flag, "CVodeSetMaxStep")?;
+        }
+        if let Some(min_step) = self.min_step {
+            let flag = unsafe { sundials_sys::CVodeSetMinStep(mem, min_step) };
+            check_flag_is_succes(flag, "CVodeSetMinStep")?;
+        }
+        if let Some(max_num_steps) = valid.max_num_steps {
+            let flag = unsafe { sundials_sys::CVodeSetMaxNumSteps(mem, max_num_steps) };
+            check_flag_is_succes(flag, "CVodeSetMaxNumSteps")?;
+        }
+        if let Some(init_step) = self.init_step {
+            let flag = unsafe { sundials_sys::CVodeSetInitStep(mem, init_step) };
+            check_flag_is_succes(flag, "CVodeSetInitStep")?;
+        }
+        if let Some(max_hnil_warns) = valid.max_hnil_warns {
+            let flag = unsafe { sundials_sys::CVodeSetMaxHnilWarns(mem, max_hnil_warns) };
+            check_flag_is_succes(flag, "CVodeSetMaxHnilWarns")?;
+        }
+        if let Some(max_err_test_fails) = valid.max_err_test_fails {
+            let flag = unsafe { sundials_sys::CVodeSetMaxErrTestFails(mem, max_err_test_fails) };
+            check_flag_is_succes(flag, "CVodeSetMaxErrTestFails")?;
+        }
+        if let Some(max_conv_fails) = valid.max_conv_fails {
+            let flag = unsafe { sundials_sys::CVodeSetMaxConvFails(mem, max_conv_fails) };
+            check_flag_is_succes(flag, "CVodeSetMaxConvFails")?;
+        }
+        Ok(())
+    }
+}
+
+/// The integer limits converted to the types of cvode.
+struct ValidLimits {
+    max_num_steps: Option<c_long>,
+    max_order: Option<c_int>,
+    max_hnil_warns: Option<c_int>,
+    max_err_test_fails: Option<c_int>,
+    max_conv_fails: Option<c_int>,
+}
+
+/// Converts the limit `limit`, set with `func_id`, to the type of cvode.
+fn checked_limit<T: TryFrom<usize>>(
+    limit: Option<usize>,
+    func_id: &'static str,
+) -> Result<Option<T>> {
+    limit
+        .map(|limit| {
+            T::try_from(limit).map_err(|_| Error::IllegalInput {
+                func_id,
+                reason: "the limit is too large",
+            })
+        })
+        .transpose()
+}
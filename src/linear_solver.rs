@@ -0,0 +1,463 @@
+use std::{convert::TryInto, os::raw::c_int};
+
+use crate::{
+    stats::{to_usize, DiagStats, PreconditionerStats},
+    sys,
+};
+
+use sundials_sys::{N_Vector, SUNLinearSolver, SUNMatrix};
+
+#[cfg(feature = "klu")]
+use crate::SparseFormat;
+use crate::{
+    check_flag_is_succes, check_non_null, CvodeMemoryBlockNonNullPtr, Error, Realtype, Result,
+};
+
+/// The orthogonalization algorithm of the GMRES solvers.
+#[repr(i32)]
+#[derive(Debug, Clone, Copy)]
+pub enum GramSchmidt {
+    /// Modified Gram-Schmidt, the default.
+    Modified = sundials_sys::MODIFIED_GS as i32,
+    /// Classical Gram-Schmidt, cheaper but less robust.
+    Classical = sundials_sys::CLASSICAL_GS as i32,
+}
+
+/// The side on which a preconditioner is applied by a [`LinearSolver::Krylov`].
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreconditionerSide {
+    Left = sundials_sys::PREC_LEFT as i32,
+    Right = sundials_sys::PREC_RIGHT as i32,
+    Both = sundials_sys::PREC_BOTH as i32,
+}
+
+/// A preconditioner for [`LinearSolver::Krylov`] provided by cvode, and
+/// built from difference quotients of the right-hand side.
+#[derive(Debug, Clone, Copy)]
+pub enum BuiltinPreconditioner {
+    /// A banded approximation of the Jacobian (`CVBANDPRE`).
+    Band {
+        upper_bandwidth: usize,
+        lower_bandwidth: usize,
+    },
+    /// A band-block-diagonal approximation of the Jacobian (`CVBBDPRE`).
+    ///
+    /// The Jacobian is approximated by difference quotients using the
+    /// `*_dq` bandwidths, and then truncated to the `*_keep` ones. `dq_rel_y`
+    /// is the relative increment in `y` used by the difference quotients,
+    /// `0.` meaning the square root of the unit roundoff.
+    BandBlockDiagonal {
+        upper_bandwidth_dq: usize,
+        lower_bandwidth_dq: usize,
+        upper_bandwidth_keep: usize,
+        lower_bandwidth_keep: usize,
+        dq_rel_y: Realtype,
+    },
+}
+
+impl BuiltinPreconditioner {
+    /// Initializes the preconditioner for a problem of size `size`, `gloc`
+    /// computing the right-hand side.
+    pub(crate) fn init(
+        &self,
+        mem: CvodeMemoryBlockNonNullPtr,
+        size: usize,
+        gloc: sys::CVLocalFn,
+    ) -> Result<()> {
+        let size = size.try_into().unwrap();
+        match *self {
+            BuiltinPreconditioner::Band {
+                upper_bandwidth,
+                lower_bandwidth,
+            } => {
+                let flag = unsafe {
+                    sys::CVBandPrecInit(
+                        mem.as_raw(),
+                        size,
+                        upper_bandwidth.try_into().unwrap(),
+                        lower_bandwidth.try_into().unwrap(),
+                    )
+                };
+                check_flag_is_succes(flag, "CVBandPrecInit")
+            }
+            BuiltinPreconditioner::BandBlockDiagonal {
+                upper_bandwidth_dq,
+                lower_bandwidth_dq,
+                upper_bandwidth_keep,
+                lower_bandwidth_keep,
+                dq_rel_y,
+            } => {
+                let flag = unsafe {
+                    sys::CVBBDPrecInit(
+                        mem.as_raw(),
+                        size,
+                        upper_bandwidth_dq.try_into().unwrap(),
+                        lower_bandwidth_dq.try_into().unwrap(),
+                        upper_bandwidth_keep.try_into().unwrap(),
+                        lower_bandwidth_keep.try_into().unwrap(),
+                        dq_rel_y,
+                        gloc,
+                        None,
+                    )
+                };
+                check_flag_is_succes(flag, "CVBBDPrecInit")
+            }
+        }
+    }
+
+    /// Returns the statistics of the preconditioner, which must have been
+    /// initialized by `self`.
+    pub(crate) fn stats(&self, mem: CvodeMemoryBlockNonNullPtr) -> Result<PreconditionerStats> {
+        let mut num_rhs_evals = 0;
+        let mut real_workspace_size = 0;
+        let mut int_workspace_size = 0;
+        match self {
+            BuiltinPreconditioner::Band { .. } => {
+                let flag =
+                    unsafe { sys::CVBandPrecGetNumRhsEvals(mem.as_raw(), &mut num_rhs_evals) };
+                check_flag_is_succes(flag, "CVBandPrecGetNumRhsEvals")?;
+                let flag = unsafe {
+                    sys::CVBandPrecGetWorkSpace(
+                        mem.as_raw(),
+                        &mut real_workspace_size,
+                        &mut int_workspace_size,
+                    )
+                };
+                check_flag_is_succes(flag, "CVBandPrecGetWorkSpace")?;
+            }
+            BuiltinPreconditioner::BandBlockDiagonal { .. } => {
+                let flag =
+                    unsafe { sys::CVBBDPrecGetNumGfnEvals(mem.as_raw(), &mut num_rhs_evals) };
+                check_flag_is_succes(flag, "CVBBDPrecGetNumGfnEvals")?;
+                let flag = unsafe {
+                    sys::CVBBDPrecGetWorkSpace(
+                        mem.as_raw(),
+                        &mut real_workspace_size,
+                        &mut int_workspace_size,
+                    )
+                };
+                check_flag_is_succes(flag, "CVBBDPrecGetWorkSpace")?;
+            }
+        }
+        Ok(PreconditionerStats {
+            num_rhs_evals: to_usize(num_rhs_evals),
+            real_workspace_size: to_usize(real_workspace_size),
+            int_workspace_size: to_usize(int_workspace_size),
+        })
+    }
+}
+
+/// Creates the linear solver of `matrix` with `create`, destroying `matrix` if
+/// it fails.
+fn create_with_matrix(
+    matrix: SUNMatrix,
+    func_id: &'static str,
+    create: impl FnOnce(SUNMatrix) -> SUNLinearSolver,
+) -> Result<(SUNMatrix, SUNLinearSolver)> {
+    match check_non_null(create(matrix), func_id) {
+        Ok(linsolver) => Ok((matrix, linsolver.as_ptr())),
+        Err(err) => {
+            unsafe { sundials_sys::SUNMatDestroy(matrix) };
+            Err(err)
+        }
+    }
+}
+
+/// An iterative method used by [`LinearSolver::Krylov`].
+#[derive(Debug, Clone, Copy)]
+pub enum KrylovMethod {
+    /// Scaled, preconditioned GMRES.
+    Spgmr { gram_schmidt: GramSchmidt },
+    /// Scaled, preconditioned flexible GMRES.
+    Spfgmr { gram_schmidt: GramSchmidt },
+    /// Scaled, preconditioned Bi-CGStab.
+    Spbcgs,
+    /// Scaled, preconditioned TFQMR.
+    Sptfqmr,
+    /// Preconditioned conjugate gradient, for symmetric linear systems only.
+    Pcg,
+}
+
+/// The linear solver used by the Newton iteration of cvode.
+#[derive(Debug, Clone, Copy)]
+pub enum LinearSolver {
+    /// A direct solver on a dense matrix.
+    Dense,
+    /// A direct solver on a banded matrix.
+    ///
+    /// The Jacobian `J` is assumed to verify `J[(i, j)] == 0.` whenever
+    /// `j > i + upper_bandwidth` or `i > j + lower_bandwidth`.
+    Band {
+        upper_bandwidth: usize,
+        lower_bandwidth: usize,
+    },
+    /// The KLU direct solver on a sparse matrix able to hold `nnz` non-zero
+    /// elements.
+    #[cfg(feature = "klu")]
+    Sparse { nnz: usize, format: SparseFormat },
+    /// A matrix-free iterative solver.
+    ///
+    /// `max_krylov_dim` is the maximum dimension of the Krylov subspace,
+    /// `0` meaning the sundials default of 5.
+    ///
+    /// `eps_lin` is the factor between the linear and nonlinear convergence
+    /// tolerances, `None` meaning the sundials default of 0.05.
+    Krylov {
+        method: KrylovMethod,
+        max_krylov_dim: usize,
+        eps_lin: Option<Realtype>,
+    },
+    /// A diagonal approximation of the Jacobian, computed by difference
+    /// quotients (`CVDIAG`).
+    ///
+    /// Neither a matrix nor a linear solver object is allocated, making it
+    /// suited to large and mildly stiff problems.
+    Diagonal,
+}
+
+impl LinearSolver {
+    /// Allocates the matrix and the linear solver for a problem of size `size`,
+    /// `y` being a template vector.
+    pub(crate) fn create(&self, y: N_Vector, size: usize) -> Result<(SUNMatrix, SUNLinearSolver)> {
+        let size = size.try_into().unwrap();
+        match *self {
+            LinearSolver::Dense => {
+                let matrix = {
+                    let matrix = unsafe { sundials_sys::SUNDenseMatrix(size, size) };
+                    check_non_null(matrix, "SUNDenseMatrix")?
+                };
+                create_with_matrix(matrix.as_ptr(), "SUNDenseLinearSolver", |matrix| unsafe {
+                    sundials_sys::SUNLinSol_Dense(y, matrix)
+                })
+            }
+            LinearSolver::Band {
+                upper_bandwidth,
+                lower_bandwidth,
+            } => {
+                let matrix = {
+                    let matrix = unsafe {
+                        sundials_sys::SUNBandMatrix(
+                            size,
+                            upper_bandwidth.try_into().unwrap(),
+                            lower_bandwidth.try_into().unwrap(),
+                        )
+                    };
+                    check_non_null(matrix, "SUNBandMatrix")?
+                };
+                create_with_matrix(matrix.as_ptr(), "SUNLinSol_Band", |matrix| unsafe {
+                    sundials_sys::SUNLinSol_Band(y, matrix)
+                })
+            }
+            #[cfg(feature = "klu")]
+            LinearSolver::Sparse { nnz, format } => {
+                let matrix = {
+                    let matrix = unsafe {
+                        sundials_sys::SUNSparseMatrix(
+                            size,
+                            size,
+                            nnz.try_into().unwrap(),
+                            format as _,
+                        )
+                    };
+                    check_non_null(matrix, "SUNSparseMatrix")?
+                };
+                create_with_matrix(matrix.as_ptr(), "SUNLinSol_KLU", |matrix| unsafe {
+                    crate::sys::SUNLinSol_KLU(y, matrix)
+                })
+            }
+            LinearSolver::Krylov {
+                method,
+                max_krylov_dim,
+                ..
+            } => {
+                let pretype = sundials_sys::PREC_NONE as c_int;
+                let maxl = max_krylov_dim.try_into().unwrap();
+                let linsolver = match method {
+                    KrylovMethod::Spgmr { gram_schmidt } => {
+                        let linsolver = unsafe { sundials_sys::SUNLinSol_SPGMR(y, pretype, maxl) };
+                        let linsolver = check_non_null(linsolver, "SUNLinSol_SPGMR")?;
+                        let flag = unsafe {
+                            sundials_sys::SUNLinSol_SPGMRSetGSType(
+                                linsolver.as_ptr(),
+                                gram_schmidt as c_int,
+                            )
+                        };
+                        if let Err(err) = check_flag_is_succes(flag, "SUNLinSol_SPGMRSetGSType") {
+                            unsafe { sundials_sys::SUNLinSolFree(linsolver.as_ptr()) };
+                            return Err(err);
+                        }
+                        linsolver
+                    }
+                    KrylovMethod::Spfgmr { gram_schmidt } => {
+                        let linsolver = unsafe { sundials_sys::SUNLinSol_SPFGMR(y, pretype, maxl) };
+                        let linsolver = check_non_null(linsolver, "SUNLinSol_SPFGMR")?;
+                        let flag = unsafe {
+                            sundials_sys::SUNLinSol_SPFGMRSetGSType(
+                                linsolver.as_ptr(),
+                                gram_schmidt as c_int,
+                            )
+                        };
+                        if let Err(err) = check_flag_is_succes(flag, "SUNLinSol_SPFGMRSetGSType") {
+                            unsafe { sundials_sys::SUNLinSolFree(linsolver.as_ptr()) };
+                            return Err(err);
+                        }
+                        linsolver
+                    }
+                    KrylovMethod::Spbcgs => {
+                        let linsolver = unsafe { sundials_sys::SUNLinSol_SPBCGS(y, pretype, maxl) };
+                        check_non_null(linsolver, "SUNLinSol_SPBCGS")?
+                    }
+                    KrylovMethod::Sptfqmr => {
+                        let linsolver =
+                            unsafe { sundials_sys::SUNLinSol_SPTFQMR(y, pretype, maxl) };
+                        check_non_null(linsolver, "SUNLinSol_SPTFQMR")?
+                    }
+                    KrylovMethod::Pcg => {
+                        let linsolver = unsafe { sundials_sys::SUNLinSol_PCG(y, pretype, maxl) };
+                        check_non_null(linsolver, "SUNLinSol_PCG")?
+                    }
+                };
+                Ok((std::ptr::null_mut(), linsolver.as_ptr()))
+            }
+            LinearSolver::Diagonal => Ok((std::ptr::null_mut(), std::ptr::null_mut())),
+        }
+    }
+
+    /// Sets the side on which the preconditioner is applied by `linsolver`,
+    /// which must have been created by `self`.
+    pub(crate) fn set_preconditioner_side(
+        &self,
+        linsolver: SUNLinearSolver,
+        side: PreconditionerSide,
+    ) -> Result<()> {
+        let pretype = side as c_int;
+        let (flag, func_id) = match *self {
+            LinearSolver::Krylov { method, .. } => match method {
+                KrylovMethod::Spgmr { .. } => (
+                    unsafe { sundials_sys::SUNLinSol_SPGMRSetPrecType(linsolver, pretype) },
+                    "SUNLinSol_SPGMRSetPrecType",
+                ),
+                KrylovMethod::Spfgmr { .. } => (
+                    unsafe { sundials_sys::SUNLinSol_SPFGMRSetPrecType(linsolver, pretype) },
+                    "SUNLinSol_SPFGMRSetPrecType",
+                ),
+                KrylovMethod::Spbcgs => (
+                    unsafe { sundials_sys::SUNLinSol_SPBCGSSetPrecType(linsolver, pretype) },
+                    "SUNLinSol_SPBCGSSetPrecType",
+                ),
+                KrylovMethod::Sptfqmr => (
+                    unsafe { sundials_sys::SUNLinSol_SPTFQMRSetPrecType(linsolver, pretype) },
+                    "SUNLinSol_SPTFQMRSetPrecType",
+                ),
+                KrylovMethod::Pcg => (
+                    unsafe { sundials_sys::SUNLinSol_PCGSetPrecType(linsolver, pretype) },
+                    "SUNLinSol_PCGSetPrecType",
+                ),
+            },
+            _ => {
+                return Err(Error::IllegalInput {
+                    func_id: "CVodeSetPreconditioner",
+                    reason: "preconditioners require a Krylov linear solver",
+                })
+            }
+        };
+        check_flag_is_succes(flag, func_id)
+    }
+
+    /// Attaches `linsolver` and `matrix`, as returned by [`LinearSolver::create`],
+    /// to the cvode memory block, and sets the options handled by cvode.
+    pub(crate) fn attach(
+        &self,
+        mem: CvodeMemoryBlockNonNullPtr,
+        linsolver: SUNLinearSolver,
+        matrix: SUNMatrix,
+    ) -> Result<()> {
+        match *self {
+            LinearSolver::Diagonal => {
+                let flag = unsafe { sys::CVDiag(mem.as_raw()) };
+                check_flag_is_succes(flag, "CVDiag")?;
+            }
+            _ => {
+                let flag =
+                    unsafe { sundials_sys::CVodeSetLinearSolver(mem.as_raw(), linsolver, matrix) };
+                check_flag_is_succes(flag, "CVodeSetLinearSolver")?;
+            }
+        }
+        if let LinearSolver::Krylov {
+            eps_lin: Some(eps_lin),
+            ..
+        } = *self
+        {
+            let flag = unsafe { sundials_sys::CVodeSetEpsLin(mem.as_raw(), eps_lin) };
+            check_flag_is_succes(flag, "CVodeSetEpsLin")?;
+        }
+        Ok(())
+    }
+
+    /// Attaches `linsolver` and `matrix`, as returned by [`LinearSolver::create`],
+    /// to the backward problem `which` of the cvode memory block, and sets the
+    /// options handled by cvode.
+    pub(crate) fn attach_backward(
+        &self,
+        mem: CvodeMemoryBlockNonNullPtr,
+        which: c_int,
+        linsolver: SUNLinearSolver,
+        matrix: SUNMatrix,
+    ) -> Result<()> {
+        match *self {
+            LinearSolver::Diagonal => {
+                let flag = unsafe { sys::CVDiagB(mem.as_raw(), which) };
+                check_flag_is_succes(flag, "CVDiagB")?;
+            }
+            _ => {
+                let flag = unsafe {
+                    sundials_sys::CVodeSetLinearSolverB(mem.as_raw(), which, linsolver, matrix)
+                };
+                check_flag_is_succes(flag, "CVodeSetLinearSolverB")?;
+            }
+        }
+        if let LinearSolver::Krylov {
+            eps_lin: Some(eps_lin),
+            ..
+        } = *self
+        {
+            let flag = unsafe { sundials_sys::CVodeSetEpsLinB(mem.as_raw(), which, eps_lin) };
+            check_flag_is_succes(flag, "CVodeSetEpsLinB")?;
+        }
+        Ok(())
+    }
+
+    /// Returns the statistics of the [`LinearSolver::Diagonal`] solver, which
+    /// must be `self`.
+    pub(crate) fn diag_stats(&self, mem: CvodeMemoryBlockNonNullPtr) -> Result<DiagStats> {
+        if !matches!(self, LinearSolver::Diagonal) {
+            return Err(Error::IllegalInput {
+                func_id: "diag_stats",
+                reason: "the linear solver is not LinearSolver::Diagonal",
+            });
+        }
+        let mut num_rhs_evals = 0;
+        let mut real_workspace_size = 0;
+        let mut int_workspace_size = 0;
+        let mut last_flag = 0;
+        let flag = unsafe { sys::CVDiagGetNumRhsEvals(mem.as_raw(), &mut num_rhs_evals) };
+        check_flag_is_succes(flag, "CVDiagGetNumRhsEvals")?;
+        let flag = unsafe {
+            sys::CVDiagGetWorkSpace(
+                mem.as_raw(),
+                &mut real_workspace_size,
+                &mut int_workspace_size,
+            )
+        };
+        check_flag_is_succes(flag, "CVDiagGetWorkSpace")?;
+        let flag = unsafe { sys::CVDiagGetLastFlag(mem.as_raw(), &mut last_flag) };
+        check_flag_is_succes(flag, "CVDiagGetLastFlag")?;
+        Ok(DiagStats {
+            num_rhs_evals: to_usize(num_rhs_evals),
+            real_workspace_size: to_usize(real_workspace_size),
+            int_workspace_size: to_usize(int_workspace_size),
+            last_flag,
+        })
+    }
+}
@@ -0,0 +1,157 @@
+use std::{convert::TryInto, os::raw::c_int};
+
+use sundials_sys::{N_Vector, SUNLinearSolver, SUNMatrix, SUNNonlinearSolver};
+
+use crate::{
+    check_flag_is_succes, check_non_null, CvodeMemoryBlockNonNullPtr, Error, LinearSolver, Result,
+};
+
+/// The nonlinear solver used by cvode at each step.
+#[derive(Debug, Clone, Copy)]
+pub enum NonlinearSolver {
+    /// Newton iteration, solving a linear system with the given solver at
+    /// each iteration. This is the cvode default, recommended for stiff
+    /// problems.
+    Newton(LinearSolver),
+    /// Fixed-point iteration, which does not need any linear solver and is
+    /// recommended for non-stiff problems.
+    ///
+    /// `anderson_depth` is the number of previous iterates used by Anderson
+    /// acceleration, `0` disabling it.
+    FixedPoint { anderson_depth: usize },
+}
+
+/// Converts the depth of the Anderson acceleration to the type of cvode, or
+/// returns an error on behalf of `func_id` if it does not fit.
+fn checked_anderson_depth(anderson_depth: usize, func_id: &'static str) -> Result<c_int> {
+    anderson_depth.try_into().map_err(|_| Error::IllegalInput {
+        func_id,
+        reason: "the depth of the Anderson acceleration is too large",
+    })
+}
+
+impl From<LinearSolver> for NonlinearSolver {
+    fn from(linear_solver: LinearSolver) -> Self {
+        NonlinearSolver::Newton(linear_solver)
+    }
+}
+
+impl NonlinearSolver {
+    /// Returns the linear solver of the Newton iteration, or an error on
+    /// behalf of `func_id` for the fixed-point iteration.
+    pub(crate) fn linear_solver(&self, func_id: &'static str) -> Result<LinearSolver> {
+        match *self {
+            NonlinearSolver::Newton(linear_solver) => Ok(linear_solver),
+            NonlinearSolver::FixedPoint { .. } => Err(Error::IllegalInput {
+                func_id,
+                reason: "the fixed-point nonlinear solver does not use a linear solver",
+            }),
+        }
+    }
+
+    /// Returns whether a linear solver is attached through the cvode linear
+    /// solver interface, which is not the case of [`LinearSolver::Diagonal`].
+    pub(crate) fn has_linear_solver(&self) -> bool {
+        match self {
+            NonlinearSolver::Newton(LinearSolver::Diagonal) => false,
+            NonlinearSolver::Newton(_) => true,
+            NonlinearSolver::FixedPoint { .. } => false,
+        }
+    }
+
+    /// Allocates the matrix, the linear solver and the nonlinear solver for a
+    /// problem of size `size`, `y` being a template vector.
+    ///
+    /// The pointers which are not needed by `self` are null.
+    pub(crate) fn create(
+        &self,
+        y: N_Vector,
+        size: usize,
+    ) -> Result<(SUNMatrix, SUNLinearSolver, SUNNonlinearSolver)> {
+        match *self {
+            NonlinearSolver::Newton(linear_solver) => {
+                let (matrix, linsolver) = linear_solver.create(y, size)?;
+                Ok((matrix, linsolver, std::ptr::null_mut()))
+            }
+            NonlinearSolver::FixedPoint { anderson_depth } => {
+                let m = checked_anderson_depth(anderson_depth, "SUNNonlinSol_FixedPoint")?;
+                let nls = unsafe { sundials_sys::SUNNonlinSol_FixedPoint(y, m) };
+                let nls = check_non_null(nls, "SUNNonlinSol_FixedPoint")?;
+                Ok((std::ptr::null_mut(), std::ptr::null_mut(), nls.as_ptr()))
+            }
+        }
+    }
+
+    /// Allocates the nonlinear solver of the `n_sensi` sensitivities, when
+    /// they are corrected in a staggered way. The pointer is null when
+    /// cvode creates it itself.
+    pub(crate) fn create_sens(&self, y: N_Vector, n_sensi: usize) -> Result<SUNNonlinearSolver> {
+        match *self {
+            NonlinearSolver::Newton(_) => Ok(std::ptr::null_mut()),
+            NonlinearSolver::FixedPoint { anderson_depth } => {
+                let m = checked_anderson_depth(anderson_depth, "SUNNonlinSol_FixedPointSens")?;
+                let nls =
+                    unsafe { sundials_sys::SUNNonlinSol_FixedPointSens(n_sensi as c_int, y, m) };
+                let nls = check_non_null(nls, "SUNNonlinSol_FixedPointSens")?;
+                Ok(nls.as_ptr())
+            }
+        }
+    }
+
+    /// Attaches the objects returned by [`NonlinearSolver::create`] to the
+    /// cvode memory block.
+    pub(crate) fn attach(
+        &self,
+        mem: CvodeMemoryBlockNonNullPtr,
+        matrix: SUNMatrix,
+        linsolver: SUNLinearSolver,
+        nls: SUNNonlinearSolver,
+    ) -> Result<()> {
+        match *self {
+            NonlinearSolver::Newton(linear_solver) => linear_solver.attach(mem, linsolver, matrix),
+            NonlinearSolver::FixedPoint { .. } => {
+                let flag = unsafe { sundials_sys::CVodeSetNonlinearSolver(mem.as_raw(), nls) };
+                check_flag_is_succes(flag, "CVodeSetNonlinearSolver")
+            }
+        }
+    }
+
+    /// Attaches the objects returned by [`NonlinearSolver::create`] to the
+    /// backward problem `which` of the cvode memory block.
+    pub(crate) fn attach_backward(
+        &self,
+        mem: CvodeMemoryBlockNonNullPtr,
+        which: c_int,
+        matrix: SUNMatrix,
+        linsolver: SUNLinearSolver,
+        nls: SUNNonlinearSolver,
+    ) -> Result<()> {
+        match *self {
+            NonlinearSolver::Newton(linear_solver) => {
+                linear_solver.attach_backward(mem, which, linsolver, matrix)
+            }
+            NonlinearSolver::FixedPoint { .. } => {
+                let flag =
+                    unsafe { sundials_sys::CVodeSetNonlinearSolverB(mem.as_raw(), which, nls) };
+                check_flag_is_succes(flag, "CVodeSetNonlinearSolverB")
+            }
+        }
+    }
+
+    /// Attaches the nonlinear solver returned by [`NonlinearSolver::create_sens`]
+    /// to the cvode memory block.
+    pub(crate) fn attach_sens(
+        &self,
+        mem: CvodeMemoryBlockNonNullPtr,
+        nls_sens: SUNNonlinearSolver,
+    ) -> Result<()> {
+        match *self {
+            NonlinearSolver::Newton(_) => Ok(()),
+            NonlinearSolver::FixedPoint { .. } => {
+                let flag =
+                    unsafe { sundials_sys::CVodeSetNonlinearSolverSensStg(mem.as_raw(), nls_sens) };
+                check_flag_is_succes(flag, "CVodeSetNonlinearSolverSensStg")
+            }
+        }
+    }
+}
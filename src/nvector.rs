@@ -88,3 +88,102 @@ impl<const SIZE: usize> Drop for NVectorSerialHeapAllocated<SIZE> {
         unsafe { sundials_sys::N_VDestroy(self.as_raw()) }
     }
 }
+
+/// A sundials `N_Vector_Serial` whose length is only known at run time.
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct NVectorSerialDyn {
+    inner: sundials_sys::_generic_N_Vector,
+}
+
+impl NVectorSerialDyn {
+    pub(crate) unsafe fn as_raw(&self) -> sundials_sys::N_Vector {
+        &self.inner as *const _ as _
+    }
+
+    /// Returns the length of the vector.
+    pub fn len(&self) -> usize {
+        unsafe { sundials_sys::N_VGetLength_Serial(self.as_raw()) }
+            .try_into()
+            .unwrap()
+    }
+
+    /// Returns whether the vector is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns a reference to the inner slice of the vector.
+    pub fn as_slice(&self) -> &[realtype] {
+        // sundials does not allocate the data of empty vectors, leaving their
+        // pointer null.
+        if self.is_empty() {
+            return &[];
+        }
+        unsafe {
+            std::slice::from_raw_parts(
+                sundials_sys::N_VGetArrayPointer_Serial(self.as_raw()),
+                self.len(),
+            )
+        }
+    }
+
+    /// Returns a mutable reference to the inner slice of the vector.
+    pub fn as_slice_mut(&mut self) -> &mut [realtype] {
+        if self.is_empty() {
+            return &mut [];
+        }
+        unsafe {
+            std::slice::from_raw_parts_mut(
+                sundials_sys::N_VGetArrayPointer_Serial(self.as_raw()),
+                self.len(),
+            )
+        }
+    }
+}
+
+#[repr(transparent)]
+#[derive(Debug)]
+/// An owning pointer to a sundials [`NVectorSerialDyn`] on the heap.
+pub struct NVectorSerialHeapAllocatedDyn {
+    inner: NonNull<NVectorSerialDyn>,
+}
+
+impl Deref for NVectorSerialHeapAllocatedDyn {
+    type Target = NVectorSerialDyn;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { self.inner.as_ref() }
+    }
+}
+
+impl DerefMut for NVectorSerialHeapAllocatedDyn {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { self.inner.as_mut() }
+    }
+}
+
+impl NVectorSerialHeapAllocatedDyn {
+    /// Creates a new vector of length `len`, filled with 0.
+    pub fn new(len: usize) -> Self {
+        let raw_c = unsafe { sundials_sys::N_VNew_Serial(len.try_into().unwrap()) };
+        let mut res = Self {
+            inner: NonNull::new(raw_c as *mut NVectorSerialDyn).unwrap(),
+        };
+        res.as_slice_mut().iter_mut().for_each(|x| *x = 0.);
+        res
+    }
+
+    /// Creates a new vector, filled with data from `data`.
+    pub fn new_from(data: &[realtype]) -> Self {
+        let mut res = Self::new(data.len());
+        res.as_slice_mut().copy_from_slice(data);
+        res
+    }
+}
+
+impl Drop for NVectorSerialHeapAllocatedDyn {
+    fn drop(&mut self) {
+        unsafe { sundials_sys::N_VDestroy(self.as_raw()) }
+    }
+}
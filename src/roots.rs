@@ -0,0 +1,41 @@
+use std::os::raw::c_int;
+
+use crate::{check_flag_is_succes, CvodeMemoryBlockNonNullPtr, Result};
+
+/// The crossings of zero of a root function component that are detected.
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RootDirection {
+    /// Only when the component is increasing.
+    Increasing = 1,
+    /// Only when the component is decreasing.
+    Decreasing = -1,
+    /// In both directions.
+    Both = 0,
+}
+
+/// The direction in which a root function component crossed zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RootCrossing {
+    Increasing,
+    Decreasing,
+}
+
+/// Returns, for each of the `num_roots` components of the root function,
+/// how it crossed zero at the last root found by cvode, if it did.
+pub(crate) fn root_info(
+    mem: CvodeMemoryBlockNonNullPtr,
+    num_roots: usize,
+) -> Result<Vec<Option<RootCrossing>>> {
+    let mut info: Vec<c_int> = vec![0; num_roots];
+    let flag = unsafe { sundials_sys::CVodeGetRootInfo(mem.as_raw(), info.as_mut_ptr()) };
+    check_flag_is_succes(flag, "CVodeGetRootInfo")?;
+    Ok(info
+        .into_iter()
+        .map(|i| match i {
+            0 => None,
+            i if i > 0 => Some(RootCrossing::Increasing),
+            _ => Some(RootCrossing::Decreasing),
+        })
+        .collect())
+}
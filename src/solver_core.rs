@@ -0,0 +1,717 @@
+//! The cvode state shared by all the solvers, whatever the way their size is
+//! known.
+
+use std::{
+    convert::{TryFrom, TryInto},
+    os::raw::{c_int, c_void},
+};
+
+use sundials_sys::{N_Vector, SUNLinearSolver, SUNMatrix, SUNNonlinearSolver, CV_STAGGERED};
+
+use crate::{
+    check_flag_is_succes, check_non_null, cvode_adjoint::AdjointInterpolation,
+    error_handler::ErrorHandler, stats, sys, BuiltinPreconditioner, CvodeFailure, CvodeMemoryBlock,
+    CvodeMemoryBlockNonNullPtr, DiagStats, Error, IntegratorStats, Limits, LinearMultistepMethod,
+    LinearSolver, NonlinearSolver, PreconditionerSide, PreconditionerStats, Realtype, Result,
+    SensStats, StepKind, StepOutcome,
+};
+
+/// Implements the methods of the solvers which only forward to their
+/// [`SolverCore`], shared by the solvers whose size is known at compile time
+/// and at run time.
+///
+/// The solvers must have a `core` field, and a pinned `user_data` field
+/// holding the `actual_user_data`. `sens` implements the methods of the
+/// solvers with sensitivities.
+macro_rules! forward_to_core {
+    () => {
+        /// Returns the user data passed to the functions called by cvode.
+        pub fn user_data(&self) -> &UserData {
+            &self.user_data.actual_user_data
+        }
+
+        /// Returns the user data passed to the functions called by cvode, to
+        /// be changed between steps.
+        pub fn user_data_mut(&mut self) -> &mut UserData {
+            unsafe { &mut self.user_data.as_mut().get_unchecked_mut().actual_user_data }
+        }
+
+        /// Returns the statistics of the preconditioner set with
+        /// [`Self::set_builtin_preconditioner`].
+        pub fn builtin_preconditioner_stats(&self) -> $crate::Result<$crate::PreconditionerStats> {
+            self.core.builtin_preconditioner_stats()
+        }
+
+        /// Returns the statistics of the integration.
+        pub fn stats(&self) -> $crate::Result<$crate::IntegratorStats> {
+            self.core.stats()
+        }
+
+        /// Returns the statistics of the linear solver, which must be
+        /// [`LinearSolver::Diagonal`]($crate::LinearSolver::Diagonal).
+        pub fn diag_stats(&self) -> $crate::Result<$crate::DiagStats> {
+            self.core.diag_stats()
+        }
+
+        /// Sets the limits of the integration which are set in `limits`, the
+        /// other ones being left unchanged.
+        pub fn set_limits(&mut self, limits: &$crate::Limits) -> $crate::Result<()> {
+            self.core.set_limits(limits)
+        }
+
+        /// Sets the stop time, past which the solver never integrates
+        /// whatever the [`StepKind`]($crate::StepKind), until it is cleared
+        /// with [`Self::clear_stop_time`]. A step ending at it returns
+        /// [`StepOutcome::StopTimeReached`]($crate::StepOutcome::StopTimeReached).
+        pub fn set_stop_time(&mut self, tstop: $crate::Realtype) -> $crate::Result<()> {
+            self.core.set_stop_time(tstop)
+        }
+
+        /// Clears the stop time set with [`Self::set_stop_time`].
+        pub fn clear_stop_time(&mut self) {
+            self.core.clear_stop_time()
+        }
+    };
+    (sens) => {
+        /// Returns the statistics of the computation of the sensitivities.
+        pub fn sens_stats(&self) -> $crate::Result<$crate::SensStats> {
+            self.core.sens_stats()
+        }
+    };
+}
+
+pub(crate) use forward_to_core;
+
+/// The cvode memory block of a solver, along with the objects attached to it.
+///
+/// The vectors and the user data are owned by the solvers themselves, which
+/// pass them as raw pointers.
+pub(crate) struct SolverCore {
+    pub(crate) mem: CvodeMemoryBlockNonNullPtr,
+    nonlinear_solver: NonlinearSolver,
+    sunmatrix: SUNMatrix,
+    linsolver: SUNLinearSolver,
+    nls: SUNNonlinearSolver,
+    nls_sens: SUNNonlinearSolver,
+    builtin_preconditioner: Option<BuiltinPreconditioner>,
+    stop_time: StopTime,
+    error_handler: ErrorHandler,
+    backward: Vec<BackwardSolvers>,
+}
+
+/// The solvers attached to a backward problem of the adjoint sensitivity
+/// analysis, whose memory block belongs to the one of the forward problem.
+struct BackwardSolvers {
+    nonlinear_solver: NonlinearSolver,
+    sunmatrix: SUNMatrix,
+    linsolver: SUNLinearSolver,
+    nls: SUNNonlinearSolver,
+}
+
+impl SolverCore {
+    /// Creates the memory block and the solvers for a problem of size `size`,
+    /// `y0` being a template vector.
+    pub(crate) fn new(
+        method: LinearMultistepMethod,
+        nonlinear_solver: NonlinearSolver,
+        y0: N_Vector,
+        size: usize,
+    ) -> Result<Self> {
+        let mem: CvodeMemoryBlockNonNullPtr = {
+            let mem_maybenull = unsafe { sundials_sys::CVodeCreate(method as c_int) };
+            check_non_null(mem_maybenull as *mut CvodeMemoryBlock, "CVodeCreate")?.into()
+        };
+        let mut res = SolverCore {
+            mem,
+            nonlinear_solver,
+            sunmatrix: std::ptr::null_mut(),
+            linsolver: std::ptr::null_mut(),
+            nls: std::ptr::null_mut(),
+            nls_sens: std::ptr::null_mut(),
+            builtin_preconditioner: None,
+            stop_time: StopTime::default(),
+            error_handler: ErrorHandler::new(),
+            backward: Vec::new(),
+        };
+        res.error_handler.install(mem)?;
+        let (matrix, linsolver, nls) = nonlinear_solver.create(y0, size)?;
+        res.sunmatrix = matrix;
+        res.linsolver = linsolver;
+        res.nls = nls;
+        Ok(res)
+    }
+
+    /// Initializes cvode with the right-hand side `f` and the initial state
+    /// `(t0, y0)`, and attaches the solvers and the user data.
+    ///
+    /// The tolerances must be set right after.
+    pub(crate) fn init(
+        &self,
+        f: sundials_sys::CVRhsFn,
+        t0: Realtype,
+        y0: N_Vector,
+        user_data: *const c_void,
+    ) -> Result<()> {
+        self.error_handler.capture(|| {
+            let flag = unsafe { sundials_sys::CVodeInit(self.mem.as_raw(), f, t0, y0) };
+            check_flag_is_succes(flag, "CVodeInit")?;
+            self.nonlinear_solver
+                .attach(self.mem, self.sunmatrix, self.linsolver, self.nls)?;
+            let flag = unsafe { sundials_sys::CVodeSetUserData(self.mem.as_raw(), user_data as _) };
+            check_flag_is_succes(flag, "CVodeSetUserData")
+        })
+    }
+
+    /// Activates the computation of `n_sensi` sensitivities, with the
+    /// right-hand side `fs` and the initial sensitivities `y_s0`.
+    ///
+    /// The tolerances of the sensitivities must be set right after.
+    pub(crate) fn init_sens(
+        &mut self,
+        n_sensi: usize,
+        fs: sundials_sys::CVSensRhsFn,
+        y_s0: *const N_Vector,
+        y0: N_Vector,
+    ) -> Result<()> {
+        let mem = self.mem;
+        self.error_handler.capture(|| {
+            let flag = unsafe {
+                sundials_sys::CVodeSensInit(
+                    mem.as_raw(),
+                    n_sensi.try_into().unwrap(),
+                    CV_STAGGERED as _,
+                    fs,
+                    y_s0 as _,
+                )
+            };
+            check_flag_is_succes(flag, "CVodeSensInit")
+        })?;
+        self.nls_sens = self.nonlinear_solver.create_sens(y0, n_sensi)?;
+        self.error_handler
+            .capture(|| self.nonlinear_solver.attach_sens(self.mem, self.nls_sens))
+    }
+
+    /// Calls `f`, attaching to its errors the messages issued by cvode
+    /// meanwhile, for the calls made by the solvers themselves, e.g. to set
+    /// the tolerances.
+    pub(crate) fn capture<T>(&self, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        self.error_handler.capture(f)
+    }
+
+    pub(crate) fn reinit(&mut self, t0: Realtype, y0: N_Vector) -> Result<()> {
+        self.error_handler.capture(|| {
+            let flag = unsafe { sundials_sys::CVodeReInit(self.mem.as_raw(), t0, y0) };
+            check_flag_is_succes(flag, "CVodeReInit")
+        })?;
+        self.stop_time.reinit();
+        Ok(())
+    }
+
+    pub(crate) fn reinit_sens(&mut self, y_s0: *const N_Vector) -> Result<()> {
+        self.error_handler.capture(|| {
+            let flag = unsafe {
+                sundials_sys::CVodeSensReInit(self.mem.as_raw(), CV_STAGGERED as _, y_s0 as _)
+            };
+            check_flag_is_succes(flag, "CVodeSensReInit")
+        })
+    }
+
+    /// Returns the linear solver of the Newton iteration, or an error on
+    /// behalf of `func_id`.
+    pub(crate) fn linear_solver(&self, func_id: &'static str) -> Result<LinearSolver> {
+        self.nonlinear_solver.linear_solver(func_id)
+    }
+
+    /// Prepares the linear solver for a user-supplied preconditioner applied
+    /// on `side`.
+    pub(crate) fn set_preconditioner_side(&mut self, side: PreconditionerSide) -> Result<()> {
+        self.linear_solver("CVodeSetPreconditioner")?
+            .set_preconditioner_side(self.linsolver, side)?;
+        self.builtin_preconditioner = None;
+        Ok(())
+    }
+
+    /// Sets a builtin preconditioner for a problem of size `size`, `gloc`
+    /// computing the right-hand side.
+    pub(crate) fn set_builtin_preconditioner(
+        &mut self,
+        side: PreconditionerSide,
+        preconditioner: BuiltinPreconditioner,
+        size: usize,
+        gloc: sys::CVLocalFn,
+    ) -> Result<()> {
+        self.linear_solver("CVodeSetPreconditioner")?
+            .set_preconditioner_side(self.linsolver, side)?;
+        preconditioner.init(self.mem, size, gloc)?;
+        self.builtin_preconditioner = Some(preconditioner);
+        Ok(())
+    }
+
+    pub(crate) fn builtin_preconditioner_stats(&self) -> Result<PreconditionerStats> {
+        match self.builtin_preconditioner {
+            Some(preconditioner) => preconditioner.stats(self.mem),
+            None => Err(Error::IllegalInput {
+                func_id: "builtin_preconditioner_stats",
+                reason: "no builtin preconditioner is set",
+            }),
+        }
+    }
+
+    pub(crate) fn stats(&self) -> Result<IntegratorStats> {
+        stats::integrator_stats(self.mem, self.nonlinear_solver.has_linear_solver())
+    }
+
+    pub(crate) fn sens_stats(&self) -> Result<SensStats> {
+        stats::sens_stats(self.mem)
+    }
+
+    pub(crate) fn diag_stats(&self) -> Result<DiagStats> {
+        self.linear_solver("diag_stats")?.diag_stats(self.mem)
+    }
+
+    /// Writes the `k`-th derivative of the interpolated state at `t` in `dky`.
+    pub(crate) fn dky(&self, t: Realtype, k: usize, dky: N_Vector) -> Result<()> {
+        let flag =
+            unsafe { sundials_sys::CVodeGetDky(self.mem.as_raw(), t, k.try_into().unwrap(), dky) };
+        check_dky_flag(flag, "CVodeGetDky")
+    }
+
+    /// Writes the `k`-th derivative of the interpolated sensitivities at `t`
+    /// in `dky`.
+    pub(crate) fn sens_dky(&self, t: Realtype, k: usize, dky: *mut N_Vector) -> Result<()> {
+        let flag = unsafe {
+            sundials_sys::CVodeGetSensDky(self.mem.as_raw(), t, k.try_into().unwrap(), dky)
+        };
+        check_dky_flag(flag, "CVodeGetSensDky")
+    }
+
+    /// Writes the `k`-th derivative of the `i`-th out of `n_sensi`
+    /// interpolated sensitivities at `t` in `dky`.
+    pub(crate) fn sens_dky1(
+        &self,
+        t: Realtype,
+        k: usize,
+        i: usize,
+        n_sensi: usize,
+        dky: N_Vector,
+    ) -> Result<()> {
+        if i >= n_sensi {
+            return Err(Error::IllegalInput {
+                func_id: "CVodeGetSensDky1",
+                reason: "the index of the sensitivity is out of range",
+            });
+        }
+        let flag = unsafe {
+            sundials_sys::CVodeGetSensDky1(
+                self.mem.as_raw(),
+                t,
+                k.try_into().unwrap(),
+                i as c_int,
+                dky,
+            )
+        };
+        check_dky_flag(flag, "CVodeGetSensDky1")
+    }
+
+    pub(crate) fn set_limits(&mut self, limits: &Limits) -> Result<()> {
+        self.error_handler.capture(|| limits.apply(self.mem))
+    }
+
+    pub(crate) fn set_stop_time(&mut self, tstop: Realtype) -> Result<()> {
+        let (mem, stop_time) = (self.mem, &mut self.stop_time);
+        self.error_handler.capture(|| stop_time.set(mem, tstop))
+    }
+
+    pub(crate) fn clear_stop_time(&mut self) {
+        self.stop_time.clear()
+    }
+
+    /// Sets the inequality constraints on the state, or removes them if
+    /// `constraints` is null.
+    pub(crate) fn set_constraints(&mut self, constraints: N_Vector) -> Result<()> {
+        self.error_handler.capture(|| {
+            let flag = unsafe { sundials_sys::CVodeSetConstraints(self.mem.as_raw(), constraints) };
+            check_flag_is_succes(flag, "CVodeSetConstraints")
+        })
+    }
+
+    /// Takes a step towards `tout`, writing the state in `y`. `num_roots` is
+    /// the number of components of the root function.
+    pub(crate) fn step(
+        &mut self,
+        tout: Realtype,
+        y: N_Vector,
+        step_kind: StepKind,
+        num_roots: usize,
+    ) -> Result<(Realtype, StepOutcome)> {
+        let (mem, stop_time) = (self.mem, &mut self.stop_time);
+        self.error_handler.capture(|| {
+            stop_time.prepare_step(mem, tout, step_kind)?;
+            let mut tret = 0.;
+            let flag =
+                unsafe { sundials_sys::CVode(mem.as_raw(), tout, y, &mut tret, step_kind.itask()) };
+            let outcome = StepOutcome::from_flag(flag, mem, num_roots)?;
+            stop_time.finish_step(&outcome);
+            Ok((tret, outcome))
+        })
+    }
+
+    /// Allocates the memory of the adjoint sensitivity analysis, the forward
+    /// solution being checkpointed every `steps` steps.
+    pub(crate) fn adj_init(&self, steps: usize, interpolation: AdjointInterpolation) -> Result<()> {
+        self.error_handler.capture(|| {
+            let flag = unsafe {
+                sundials_sys::CVodeAdjInit(
+                    self.mem.as_raw(),
+                    steps.try_into().unwrap(),
+                    interpolation as c_int,
+                )
+            };
+            check_flag_is_succes(flag, "CVodeAdjInit")
+        })
+    }
+
+    /// Takes a step of the forward problem towards `tout`, writing the state
+    /// in `y` and storing the checkpoints needed by the backward problems.
+    pub(crate) fn step_forward(
+        &mut self,
+        tout: Realtype,
+        y: N_Vector,
+        step_kind: StepKind,
+    ) -> Result<(Realtype, StepOutcome)> {
+        let (mem, stop_time) = (self.mem, &mut self.stop_time);
+        self.error_handler.capture(|| {
+            stop_time.prepare_step(mem, tout, step_kind)?;
+            let mut tret = 0.;
+            let mut num_checkpoints = 0;
+            let flag = unsafe {
+                sundials_sys::CVodeF(
+                    mem.as_raw(),
+                    tout,
+                    y,
+                    &mut tret,
+                    step_kind.itask(),
+                    &mut num_checkpoints,
+                )
+            };
+            let outcome = StepOutcome::from_flag(flag, mem, 0)?;
+            stop_time.finish_step(&outcome);
+            Ok((tret, outcome))
+        })
+    }
+
+    /// Creates a backward problem of size `size`, integrating `f_b` from the
+    /// final state `(tb0, yb0)`, and attaches its solvers and user data.
+    ///
+    /// Returns the index of the problem, whose tolerances must be set right
+    /// after.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn init_backward(
+        &mut self,
+        method: LinearMultistepMethod,
+        nonlinear_solver: NonlinearSolver,
+        f_b: sundials_sys::CVRhsFnB,
+        tb0: Realtype,
+        yb0: N_Vector,
+        size: usize,
+        user_data: *const c_void,
+    ) -> Result<c_int> {
+        let (mem, backward) = (self.mem, &mut self.backward);
+        let which = self.error_handler.capture(|| {
+            let mut which = 0;
+            let flag =
+                unsafe { sundials_sys::CVodeCreateB(mem.as_raw(), method as c_int, &mut which) };
+            check_flag_is_succes(flag, "CVodeCreateB")?;
+            // Pushed right away, so that the solvers of the problem `which`
+            // are always `backward[which]`.
+            backward.push(BackwardSolvers {
+                nonlinear_solver,
+                sunmatrix: std::ptr::null_mut(),
+                linsolver: std::ptr::null_mut(),
+                nls: std::ptr::null_mut(),
+            });
+            let flag = unsafe { sundials_sys::CVodeInitB(mem.as_raw(), which, f_b, tb0, yb0) };
+            check_flag_is_succes(flag, "CVodeInitB")?;
+            let (matrix, linsolver, nls) = nonlinear_solver.create(yb0, size)?;
+            let solvers = backward.last_mut().unwrap();
+            solvers.sunmatrix = matrix;
+            solvers.linsolver = linsolver;
+            solvers.nls = nls;
+            nonlinear_solver.attach_backward(mem, which, matrix, linsolver, nls)?;
+            let flag =
+                unsafe { sundials_sys::CVodeSetUserDataB(mem.as_raw(), which, user_data as _) };
+            check_flag_is_succes(flag, "CVodeSetUserDataB")?;
+            Ok(which)
+        })?;
+        let mem_b = self.backward_mem(which)?;
+        self.error_handler.install(mem_b)?;
+        Ok(which)
+    }
+
+    /// Checks that the backward problem `which` exists, or returns an error
+    /// on behalf of `func_id`.
+    ///
+    /// cvode only checks the upper bound of `which`, and only in some of its
+    /// functions.
+    fn check_backward(&self, which: c_int, func_id: &'static str) -> Result<usize> {
+        match usize::try_from(which) {
+            Ok(index) if index < self.backward.len() => Ok(index),
+            _ => Err(Error::IllegalInput {
+                func_id,
+                reason: "there is no such backward problem",
+            }),
+        }
+    }
+
+    /// Returns the memory block of the backward problem `which`.
+    fn backward_mem(&self, which: c_int) -> Result<CvodeMemoryBlockNonNullPtr> {
+        self.check_backward(which, "CVodeGetAdjCVodeBmem")?;
+        let mem_b = unsafe { sundials_sys::CVodeGetAdjCVodeBmem(self.mem.as_raw(), which) };
+        Ok(check_non_null(mem_b as *mut CvodeMemoryBlock, "CVodeGetAdjCVodeBmem")?.into())
+    }
+
+    /// Returns the linear solver of the Newton iteration of the backward
+    /// problem `which`, or an error on behalf of `func_id`.
+    pub(crate) fn backward_linear_solver(
+        &self,
+        which: c_int,
+        func_id: &'static str,
+    ) -> Result<LinearSolver> {
+        self.backward[self.check_backward(which, func_id)?]
+            .nonlinear_solver
+            .linear_solver(func_id)
+    }
+
+    pub(crate) fn set_backward_jac_fn(
+        &mut self,
+        which: c_int,
+        jac_fn: sundials_sys::CVLsJacFnB,
+    ) -> Result<()> {
+        self.check_backward(which, "CVodeSetJacFnB")?;
+        self.error_handler.capture(|| {
+            let flag = unsafe { sundials_sys::CVodeSetJacFnB(self.mem.as_raw(), which, jac_fn) };
+            check_flag_is_succes(flag, "CVodeSetJacFnB")
+        })
+    }
+
+    pub(crate) fn backward_stats(&self, which: c_int) -> Result<IntegratorStats> {
+        stats::integrator_stats(
+            self.backward_mem(which)?,
+            self.backward[which as usize]
+                .nonlinear_solver
+                .has_linear_solver(),
+        )
+    }
+
+    /// Integrates all the backward problems towards `tb_out`.
+    pub(crate) fn step_backward(&mut self, tb_out: Realtype, step_kind: StepKind) -> Result<()> {
+        if step_kind.stops_at_stop_time() {
+            return Err(Error::IllegalInput {
+                func_id: "CVodeB",
+                reason: "backward problems do not have a stop time",
+            });
+        }
+        self.error_handler.capture(|| {
+            let flag =
+                unsafe { sundials_sys::CVodeB(self.mem.as_raw(), tb_out, step_kind.itask()) };
+            if flag >= 0 {
+                return Ok(());
+            }
+            // cvode does not tell which problem failed, so the time reached
+            // is the one of the problem farthest from `tb_out`.
+            let mut t = tb_out;
+            for which in 0..self.backward.len() {
+                let t_b = self.backward_time(which as c_int)?;
+                if (t_b - tb_out).abs() > (t - tb_out).abs() {
+                    t = t_b;
+                }
+            }
+            Err(Error::StepFailed {
+                t,
+                failure: CvodeFailure::from_flag(flag),
+                message: None,
+            })
+        })
+    }
+
+    /// Returns the time reached by the backward problem `which`.
+    fn backward_time(&self, which: c_int) -> Result<Realtype> {
+        let mut t = 0.;
+        let flag = unsafe {
+            sundials_sys::CVodeGetCurrentTime(self.backward_mem(which)?.as_raw(), &mut t)
+        };
+        check_flag_is_succes(flag, "CVodeGetCurrentTime")?;
+        Ok(t)
+    }
+
+    /// Activates the quadrature of the backward problem `which`, with the
+    /// right-hand side `f_qb` and the initial value `yqb0`, including it in
+    /// the error control.
+    ///
+    /// The tolerances of the quadrature must be set right after.
+    pub(crate) fn init_backward_quad(
+        &mut self,
+        which: c_int,
+        f_qb: sundials_sys::CVQuadRhsFnB,
+        yqb0: N_Vector,
+    ) -> Result<()> {
+        self.check_backward(which, "CVodeQuadInitB")?;
+        self.error_handler.capture(|| {
+            let flag =
+                unsafe { sundials_sys::CVodeQuadInitB(self.mem.as_raw(), which, f_qb, yqb0) };
+            check_flag_is_succes(flag, "CVodeQuadInitB")?;
+            let flag =
+                unsafe { sundials_sys::CVodeSetQuadErrConB(self.mem.as_raw(), which, true as _) };
+            check_flag_is_succes(flag, "CVodeSetQuadErrConB")
+        })
+    }
+
+    /// Writes the quadrature of the backward problem `which` in `q_b`, and
+    /// returns the time it was reached at.
+    pub(crate) fn get_backward_quad(&self, which: c_int, q_b: N_Vector) -> Result<Realtype> {
+        self.check_backward(which, "CVodeGetQuadB")?;
+        self.error_handler.capture(|| {
+            let mut tret = 0.;
+            let flag =
+                unsafe { sundials_sys::CVodeGetQuadB(self.mem.as_raw(), which, &mut tret, q_b) };
+            check_flag_is_succes(flag, "CVodeGetQuadB")?;
+            Ok(tret)
+        })
+    }
+
+    /// Writes the state of the backward problem `which` in `y_b`, and returns
+    /// the time it was reached at.
+    pub(crate) fn get_backward(&self, which: c_int, y_b: N_Vector) -> Result<Realtype> {
+        self.check_backward(which, "CVodeGetB")?;
+        self.error_handler.capture(|| {
+            let mut tret = 0.;
+            let flag = unsafe { sundials_sys::CVodeGetB(self.mem.as_raw(), which, &mut tret, y_b) };
+            if flag < 0 {
+                return Err(Error::StepFailed {
+                    t: self.backward_time(which)?,
+                    failure: CvodeFailure::from_flag(flag),
+                    message: None,
+                });
+            }
+            Ok(tret)
+        })
+    }
+
+    /// Writes the sensitivities at the time reached by the last step in `y_s`.
+    pub(crate) fn get_sens(&self, y_s: *mut N_Vector) -> Result<()> {
+        self.error_handler.capture(|| {
+            let mut tret = 0.;
+            let flag = unsafe { sundials_sys::CVodeGetSens(self.mem.as_raw(), &mut tret, y_s) };
+            check_flag_is_succes(flag, "CVodeGetSens")
+        })
+    }
+}
+
+impl Drop for SolverCore {
+    fn drop(&mut self) {
+        unsafe { sundials_sys::CVodeFree(&mut self.mem.as_raw()) }
+        unsafe { sundials_sys::SUNLinSolFree(self.linsolver) };
+        unsafe { sundials_sys::SUNMatDestroy(self.sunmatrix) };
+        unsafe { sundials_sys::SUNNonlinSolFree(self.nls) };
+        unsafe { sundials_sys::SUNNonlinSolFree(self.nls_sens) };
+        for backward in &self.backward {
+            unsafe { sundials_sys::SUNLinSolFree(backward.linsolver) };
+            unsafe { sundials_sys::SUNMatDestroy(backward.sunmatrix) };
+            unsafe { sundials_sys::SUNNonlinSolFree(backward.nls) };
+        }
+    }
+}
+
+/// The stop time of a solver.
+///
+/// cvode forgets its stop time once it has been reached, so it is set again
+/// before the following steps until it is cleared. cvode cannot clear a stop
+/// time, which is instead pushed to infinity in the direction of the
+/// integration.
+#[derive(Debug, Clone, Copy, Default)]
+struct StopTime {
+    value: Option<Realtype>,
+    /// The stop time held by cvode, which has not been reached yet.
+    held: Option<Realtype>,
+}
+
+impl StopTime {
+    fn set(&mut self, mem: CvodeMemoryBlockNonNullPtr, tstop: Realtype) -> Result<()> {
+        self.set_held(mem, tstop)?;
+        self.value = Some(tstop);
+        Ok(())
+    }
+
+    fn set_held(&mut self, mem: CvodeMemoryBlockNonNullPtr, tstop: Realtype) -> Result<()> {
+        let flag = unsafe { sundials_sys::CVodeSetStopTime(mem.as_raw(), tstop) };
+        check_flag_is_succes(flag, "CVodeSetStopTime")?;
+        self.held = Some(tstop);
+        Ok(())
+    }
+
+    fn clear(&mut self) {
+        self.value = None;
+    }
+
+    /// cvode keeps its stop time when reinitialized, but it is set again
+    /// before the next step, to be checked against the new initial time.
+    fn reinit(&mut self) {
+        if self.value.is_some() {
+            self.held = None;
+        }
+    }
+
+    /// Sets the stop time of cvode before a step of kind `step_kind` towards
+    /// `tout`: again if it has been reached, or to infinity if it has been
+    /// cleared.
+    fn prepare_step(
+        &mut self,
+        mem: CvodeMemoryBlockNonNullPtr,
+        tout: Realtype,
+        step_kind: StepKind,
+    ) -> Result<()> {
+        match (self.value, self.held) {
+            (Some(tstop), held) if held != Some(tstop) => self.set_held(mem, tstop),
+            (Some(_), _) => Ok(()),
+            (None, _) if step_kind.stops_at_stop_time() => Err(Error::IllegalInput {
+                func_id: "CVode",
+                reason: "a Tstop step kind requires a stop time",
+            }),
+            (None, None) => Ok(()),
+            (None, Some(held)) => {
+                let mut tcur = 0.;
+                let flag = unsafe { sundials_sys::CVodeGetCurrentTime(mem.as_raw(), &mut tcur) };
+                check_flag_is_succes(flag, "CVodeGetCurrentTime")?;
+                let infinity = Realtype::INFINITY.copysign(tout - tcur);
+                if held == infinity {
+                    Ok(())
+                } else {
+                    self.set_held(mem, infinity)
+                }
+            }
+        }
+    }
+
+    /// Records that cvode has forgotten its stop time if the step ended at it.
+    fn finish_step(&mut self, outcome: &StepOutcome) {
+        if *outcome == StepOutcome::StopTimeReached {
+            self.held = None;
+        }
+    }
+}
+
+/// Checks the flag returned by the `CVodeGet*Dky*` functions, reporting
+/// out-of-range arguments as [`Error::IllegalInput`].
+fn check_dky_flag(flag: c_int, func_id: &'static str) -> Result<()> {
+    match flag {
+        sundials_sys::CV_BAD_K => Err(Error::IllegalInput {
+            func_id,
+            reason: "the order of the derivative exceeds the current order of the method",
+        }),
+        sundials_sys::CV_BAD_T => Err(Error::IllegalInput {
+            func_id,
+            reason: "the time is outside of the last internal step",
+        }),
+        flag => check_flag_is_succes(flag, func_id),
+    }
+}
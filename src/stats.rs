@@ -0,0 +1,265 @@
+use std::{
+    convert::TryInto,
+    os::raw::{c_int, c_long},
+};
+
+use crate::{check_flag_is_succes, CvodeMemoryBlockNonNullPtr, Realtype, Result};
+
+/// Statistics of a [`BuiltinPreconditioner`](crate::BuiltinPreconditioner).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PreconditionerStats {
+    /// Number of right-hand side evaluations used to build the preconditioner.
+    pub num_rhs_evals: usize,
+    /// Size of the real workspace, in `Realtype` words.
+    pub real_workspace_size: usize,
+    /// Size of the integer workspace, in `Indextype` words.
+    pub int_workspace_size: usize,
+}
+
+pub(crate) fn to_usize(x: c_long) -> usize {
+    x.try_into().unwrap()
+}
+
+/// Statistics of the [`LinearSolver::Diagonal`](crate::LinearSolver::Diagonal)
+/// linear solver.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DiagStats {
+    /// Number of right-hand side evaluations used to approximate the Jacobian.
+    pub num_rhs_evals: usize,
+    /// Size of the real workspace, in `Realtype` words.
+    pub real_workspace_size: usize,
+    /// Size of the integer workspace, in `Indextype` words.
+    pub int_workspace_size: usize,
+    /// The last return flag of the diagonal linear solver, `0` meaning success.
+    pub last_flag: c_long,
+}
+
+/// Statistics of the integration since the solver was created.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IntegratorStats {
+    /// Number of internal steps taken.
+    pub num_steps: usize,
+    /// Number of calls to the right-hand side function.
+    pub num_rhs_evals: usize,
+    /// Number of calls to the setup function of the linear solver.
+    pub num_lin_solv_setups: usize,
+    /// Number of local error test failures.
+    pub num_err_test_fails: usize,
+    /// Order of the method used on the last step.
+    pub last_order: usize,
+    /// Order of the method to be used on the next step.
+    pub current_order: usize,
+    /// Step size used on the first step.
+    pub actual_init_step: Realtype,
+    /// Step size used on the last step.
+    pub last_step: Realtype,
+    /// Step size to be attempted on the next step.
+    pub current_step: Realtype,
+    /// Current internal time reached by the solver.
+    pub current_time: Realtype,
+    /// Number of nonlinear solver iterations.
+    pub num_nonlin_iters: usize,
+    /// Number of nonlinear solver convergence failures.
+    pub num_nonlin_conv_fails: usize,
+    /// Statistics of the linear solver, when the Newton iteration is used
+    /// with a linear solver other than
+    /// [`LinearSolver::Diagonal`](crate::LinearSolver::Diagonal).
+    pub linear_solver: Option<LinearSolverStats>,
+}
+
+/// Statistics of the linear solver used by the Newton iteration.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LinearSolverStats {
+    /// Number of Jacobian evaluations.
+    pub num_jac_evals: usize,
+    /// Number of right-hand side evaluations used by difference quotients.
+    pub num_rhs_evals: usize,
+    /// Number of iterations of a Krylov linear solver.
+    pub num_lin_iters: usize,
+    /// Number of convergence failures of a Krylov linear solver.
+    pub num_lin_conv_fails: usize,
+    /// Number of preconditioner evaluations.
+    pub num_prec_evals: usize,
+    /// Number of calls to the preconditioner solve function.
+    pub num_prec_solves: usize,
+    /// Number of calls to the Jacobian-times-vector setup function.
+    pub num_jac_times_setups: usize,
+    /// Number of Jacobian-times-vector products.
+    pub num_jac_times_evals: usize,
+}
+
+/// Statistics of the computation of the sensitivities.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SensStats {
+    /// Number of calls to the sensitivities right-hand side function.
+    pub num_sens_rhs_evals: usize,
+    /// Number of calls to the right-hand side function made for the
+    /// sensitivities.
+    pub num_rhs_evals: usize,
+    /// Number of local error test failures of the sensitivities.
+    pub num_err_test_fails: usize,
+    /// Number of calls to the setup function of the linear solver made for
+    /// the sensitivities.
+    pub num_lin_solv_setups: usize,
+    /// Number of nonlinear solver iterations for the sensitivities.
+    pub num_nonlin_iters: usize,
+    /// Number of nonlinear solver convergence failures for the sensitivities.
+    pub num_nonlin_conv_fails: usize,
+}
+
+fn c_int_to_usize(x: c_int) -> usize {
+    x.try_into().unwrap()
+}
+
+/// Reads a counter of cvode with the getter `func`, named `func_id`.
+fn get_counter(
+    mem: CvodeMemoryBlockNonNullPtr,
+    func: unsafe extern "C" fn(*mut std::os::raw::c_void, *mut c_long) -> c_int,
+    func_id: &'static str,
+) -> Result<usize> {
+    let mut counter = 0;
+    let flag = unsafe { func(mem.as_raw(), &mut counter) };
+    check_flag_is_succes(flag, func_id)?;
+    Ok(to_usize(counter))
+}
+
+/// Returns the statistics of the integration, including those of the linear
+/// solver if `has_linear_solver`.
+pub(crate) fn integrator_stats(
+    mem: CvodeMemoryBlockNonNullPtr,
+    has_linear_solver: bool,
+) -> Result<IntegratorStats> {
+    let mut num_steps = 0;
+    let mut num_rhs_evals = 0;
+    let mut num_lin_solv_setups = 0;
+    let mut num_err_test_fails = 0;
+    let mut last_order = 0;
+    let mut current_order = 0;
+    let mut actual_init_step = 0.;
+    let mut last_step = 0.;
+    let mut current_step = 0.;
+    let mut current_time = 0.;
+    let flag = unsafe {
+        sundials_sys::CVodeGetIntegratorStats(
+            mem.as_raw(),
+            &mut num_steps,
+            &mut num_rhs_evals,
+            &mut num_lin_solv_setups,
+            &mut num_err_test_fails,
+            &mut last_order,
+            &mut current_order,
+            &mut actual_init_step,
+            &mut last_step,
+            &mut current_step,
+            &mut current_time,
+        )
+    };
+    check_flag_is_succes(flag, "CVodeGetIntegratorStats")?;
+    let mut num_nonlin_iters = 0;
+    let mut num_nonlin_conv_fails = 0;
+    let flag = unsafe {
+        sundials_sys::CVodeGetNonlinSolvStats(
+            mem.as_raw(),
+            &mut num_nonlin_iters,
+            &mut num_nonlin_conv_fails,
+        )
+    };
+    check_flag_is_succes(flag, "CVodeGetNonlinSolvStats")?;
+    let linear_solver = if has_linear_solver {
+        Some(LinearSolverStats {
+            num_jac_evals: get_counter(
+                mem,
+                sundials_sys::CVodeGetNumJacEvals,
+                "CVodeGetNumJacEvals",
+            )?,
+            num_rhs_evals: get_counter(
+                mem,
+                sundials_sys::CVodeGetNumLinRhsEvals,
+                "CVodeGetNumLinRhsEvals",
+            )?,
+            num_lin_iters: get_counter(
+                mem,
+                sundials_sys::CVodeGetNumLinIters,
+                "CVodeGetNumLinIters",
+            )?,
+            num_lin_conv_fails: get_counter(
+                mem,
+                sundials_sys::CVodeGetNumLinConvFails,
+                "CVodeGetNumLinConvFails",
+            )?,
+            num_prec_evals: get_counter(
+                mem,
+                sundials_sys::CVodeGetNumPrecEvals,
+                "CVodeGetNumPrecEvals",
+            )?,
+            num_prec_solves: get_counter(
+                mem,
+                sundials_sys::CVodeGetNumPrecSolves,
+                "CVodeGetNumPrecSolves",
+            )?,
+            num_jac_times_setups: get_counter(
+                mem,
+                sundials_sys::CVodeGetNumJTSetupEvals,
+                "CVodeGetNumJTSetupEvals",
+            )?,
+            num_jac_times_evals: get_counter(
+                mem,
+                sundials_sys::CVodeGetNumJtimesEvals,
+                "CVodeGetNumJtimesEvals",
+            )?,
+        })
+    } else {
+        None
+    };
+    Ok(IntegratorStats {
+        num_steps: to_usize(num_steps),
+        num_rhs_evals: to_usize(num_rhs_evals),
+        num_lin_solv_setups: to_usize(num_lin_solv_setups),
+        num_err_test_fails: to_usize(num_err_test_fails),
+        last_order: c_int_to_usize(last_order),
+        current_order: c_int_to_usize(current_order),
+        actual_init_step,
+        last_step,
+        current_step,
+        current_time,
+        num_nonlin_iters: to_usize(num_nonlin_iters),
+        num_nonlin_conv_fails: to_usize(num_nonlin_conv_fails),
+        linear_solver,
+    })
+}
+
+/// Returns the statistics of the sensitivities.
+pub(crate) fn sens_stats(mem: CvodeMemoryBlockNonNullPtr) -> Result<SensStats> {
+    let mut num_sens_rhs_evals = 0;
+    let mut num_rhs_evals = 0;
+    let mut num_err_test_fails = 0;
+    let mut num_lin_solv_setups = 0;
+    let flag = unsafe {
+        sundials_sys::CVodeGetSensStats(
+            mem.as_raw(),
+            &mut num_sens_rhs_evals,
+            &mut num_rhs_evals,
+            &mut num_err_test_fails,
+            &mut num_lin_solv_setups,
+        )
+    };
+    check_flag_is_succes(flag, "CVodeGetSensStats")?;
+    let mut num_nonlin_iters = 0;
+    let mut num_nonlin_conv_fails = 0;
+    let flag = unsafe {
+        sundials_sys::CVodeGetSensNonlinSolvStats(
+            mem.as_raw(),
+            &mut num_nonlin_iters,
+            &mut num_nonlin_conv_fails,
+        )
+    };
+    check_flag_is_succes(flag, "CVodeGetSensNonlinSolvStats")?;
+    Ok(SensStats {
+        num_sens_rhs_evals: to_usize(num_sens_rhs_evals),
+        num_rhs_evals: to_usize(num_rhs_evals),
+        num_err_test_fails: to_usize(num_err_test_fails),
+        num_lin_solv_setups: to_usize(num_lin_solv_setups),
+        num_nonlin_iters: to_usize(num_nonlin_iters),
+        num_nonlin_conv_fails: to_usize(num_nonlin_conv_fails),
+    })
+}
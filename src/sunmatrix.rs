@@ -0,0 +1,223 @@
+use std::{
+    convert::TryInto,
+    ops::{Index, IndexMut},
+};
+
+use sundials_sys::realtype;
+
+#[cfg(feature = "klu")]
+use crate::Indextype;
+
+/// A view over a sundials dense `SUNMatrix` of size `SIZE * SIZE`.
+///
+/// The underlying storage is column-major.
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct DenseMatrixView<const SIZE: usize> {
+    inner: sundials_sys::_generic_SUNMatrix,
+}
+
+impl<const SIZE: usize> DenseMatrixView<SIZE> {
+    pub(crate) unsafe fn as_raw(&self) -> sundials_sys::SUNMatrix {
+        &self.inner as *const _ as *mut _
+    }
+
+    /// Returns a reference to the `j`-th column of the matrix.
+    pub fn column(&self, j: usize) -> &[realtype; SIZE] {
+        assert!(j < SIZE);
+        unsafe {
+            &*(sundials_sys::SUNDenseMatrix_Column(self.as_raw(), j.try_into().unwrap())
+                as *const [realtype; SIZE])
+        }
+    }
+
+    /// Returns a mutable reference to the `j`-th column of the matrix.
+    pub fn column_mut(&mut self, j: usize) -> &mut [realtype; SIZE] {
+        assert!(j < SIZE);
+        unsafe {
+            &mut *(sundials_sys::SUNDenseMatrix_Column(self.as_raw(), j.try_into().unwrap())
+                as *mut [realtype; SIZE])
+        }
+    }
+}
+
+/// Indexing is done with `(row, column)`.
+impl<const SIZE: usize> Index<(usize, usize)> for DenseMatrixView<SIZE> {
+    type Output = realtype;
+
+    fn index(&self, (i, j): (usize, usize)) -> &Self::Output {
+        &self.column(j)[i]
+    }
+}
+
+impl<const SIZE: usize> IndexMut<(usize, usize)> for DenseMatrixView<SIZE> {
+    fn index_mut(&mut self, (i, j): (usize, usize)) -> &mut Self::Output {
+        &mut self.column_mut(j)[i]
+    }
+}
+
+/// A view over a sundials banded `SUNMatrix` of size `SIZE * SIZE`.
+///
+/// Only the elements within the band can be accessed: `(i, j)` is in the
+/// band if `j <= i + upper_bandwidth` and `i <= j + lower_bandwidth`.
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct BandMatrixView<const SIZE: usize> {
+    inner: sundials_sys::_generic_SUNMatrix,
+}
+
+impl<const SIZE: usize> BandMatrixView<SIZE> {
+    pub(crate) unsafe fn as_raw(&self) -> sundials_sys::SUNMatrix {
+        &self.inner as *const _ as *mut _
+    }
+
+    /// Returns the upper bandwidth of the matrix.
+    pub fn upper_bandwidth(&self) -> usize {
+        unsafe { sundials_sys::SUNBandMatrix_UpperBandwidth(self.as_raw()) }
+            .try_into()
+            .unwrap()
+    }
+
+    /// Returns the lower bandwidth of the matrix.
+    pub fn lower_bandwidth(&self) -> usize {
+        unsafe { sundials_sys::SUNBandMatrix_LowerBandwidth(self.as_raw()) }
+            .try_into()
+            .unwrap()
+    }
+
+    /// Returns whether `(i, j)` is within the band.
+    pub fn in_band(&self, i: usize, j: usize) -> bool {
+        i < SIZE && j < SIZE && j <= i + self.upper_bandwidth() && i <= j + self.lower_bandwidth()
+    }
+
+    fn element_ptr(&self, i: usize, j: usize) -> *mut realtype {
+        assert!(
+            self.in_band(i, j),
+            "({}, {}) is outside of the band of the matrix",
+            i,
+            j
+        );
+        unsafe {
+            sundials_sys::SUNBandMatrix_Column(self.as_raw(), j.try_into().unwrap())
+                .offset(i as isize - j as isize)
+        }
+    }
+}
+
+/// Indexing is done with `(row, column)`, and panics outside of the band.
+impl<const SIZE: usize> Index<(usize, usize)> for BandMatrixView<SIZE> {
+    type Output = realtype;
+
+    fn index(&self, (i, j): (usize, usize)) -> &Self::Output {
+        unsafe { &*self.element_ptr(i, j) }
+    }
+}
+
+impl<const SIZE: usize> IndexMut<(usize, usize)> for BandMatrixView<SIZE> {
+    fn index_mut(&mut self, (i, j): (usize, usize)) -> &mut Self::Output {
+        unsafe { &mut *self.element_ptr(i, j) }
+    }
+}
+
+/// The storage format of a sparse matrix.
+#[cfg(feature = "klu")]
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SparseFormat {
+    /// Compressed sparse column: `index_pointers` delimits the columns, and
+    /// `index_values` contains row indices.
+    Csc = sundials_sys::CSC_MAT as i32,
+    /// Compressed sparse row: `index_pointers` delimits the rows, and
+    /// `index_values` contains column indices.
+    Csr = sundials_sys::CSR_MAT as i32,
+}
+
+/// A view over a sundials sparse `SUNMatrix` of size `SIZE * SIZE`.
+///
+/// The non-zero elements of the `k`-th column (resp. row) for the
+/// [`SparseFormat::Csc`] (resp. [`SparseFormat::Csr`]) format are stored in
+/// `data[index_pointers[k]..index_pointers[k + 1]]`, and their row
+/// (resp. column) indices in the same range of `index_values`.
+#[cfg(feature = "klu")]
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct SparseMatrixView<const SIZE: usize> {
+    inner: sundials_sys::_generic_SUNMatrix,
+}
+
+#[cfg(feature = "klu")]
+impl<const SIZE: usize> SparseMatrixView<SIZE> {
+    pub(crate) unsafe fn as_raw(&self) -> sundials_sys::SUNMatrix {
+        &self.inner as *const _ as *mut _
+    }
+
+    /// Returns the storage format of the matrix.
+    pub fn format(&self) -> SparseFormat {
+        if unsafe { sundials_sys::SUNSparseMatrix_SparseType(self.as_raw()) }
+            == SparseFormat::Csc as i32
+        {
+            SparseFormat::Csc
+        } else {
+            SparseFormat::Csr
+        }
+    }
+
+    /// Returns the maximum number of non-zero elements the matrix can hold.
+    pub fn nnz(&self) -> usize {
+        unsafe { sundials_sys::SUNSparseMatrix_NNZ(self.as_raw()) }
+            .try_into()
+            .unwrap()
+    }
+
+    /// Returns the `SIZE + 1` index pointers.
+    pub fn index_pointers(&self) -> &[Indextype] {
+        unsafe {
+            std::slice::from_raw_parts(
+                sundials_sys::SUNSparseMatrix_IndexPointers(self.as_raw()),
+                SIZE + 1,
+            )
+        }
+    }
+
+    /// Returns the `nnz` index values.
+    pub fn index_values(&self) -> &[Indextype] {
+        unsafe {
+            std::slice::from_raw_parts(
+                sundials_sys::SUNSparseMatrix_IndexValues(self.as_raw()),
+                self.nnz(),
+            )
+        }
+    }
+
+    /// Returns the `nnz` stored values.
+    pub fn data(&self) -> &[realtype] {
+        unsafe {
+            std::slice::from_raw_parts(
+                sundials_sys::SUNSparseMatrix_Data(self.as_raw()),
+                self.nnz(),
+            )
+        }
+    }
+
+    /// Returns mutable references to `(index_pointers, index_values, data)`,
+    /// so that they can be filled simultaneously.
+    pub fn parts_mut(&mut self) -> (&mut [Indextype], &mut [Indextype], &mut [realtype]) {
+        let nnz = self.nnz();
+        unsafe {
+            (
+                std::slice::from_raw_parts_mut(
+                    sundials_sys::SUNSparseMatrix_IndexPointers(self.as_raw()),
+                    SIZE + 1,
+                ),
+                std::slice::from_raw_parts_mut(
+                    sundials_sys::SUNSparseMatrix_IndexValues(self.as_raw()),
+                    nnz,
+                ),
+                std::slice::from_raw_parts_mut(
+                    sundials_sys::SUNSparseMatrix_Data(self.as_raw()),
+                    nnz,
+                ),
+            )
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! Declarations of sundials functions not exposed by `sundials-sys`.
+#![allow(non_snake_case)]
+
+use std::os::raw::{c_int, c_long, c_void};
+
+use sundials_sys::{realtype, sunindextype, N_Vector};
+#[cfg(feature = "klu")]
+use sundials_sys::{SUNLinearSolver, SUNMatrix};
+
+#[cfg(feature = "klu")]
+#[link(name = "sundials_sunlinsolklu")]
+extern "C" {
+    pub(crate) fn SUNLinSol_KLU(y: N_Vector, A: SUNMatrix) -> SUNLinearSolver;
+}
+
+// stdlib.h, to free the strings allocated by sundials
+extern "C" {
+    pub(crate) fn free(ptr: *mut c_void);
+}
+
+// cvodes/cvodes_bandpre.h
+extern "C" {
+    pub(crate) fn CVBandPrecInit(
+        cvode_mem: *mut c_void,
+        N: sunindextype,
+        mu: sunindextype,
+        ml: sunindextype,
+    ) -> c_int;
+    pub(crate) fn CVBandPrecGetWorkSpace(
+        cvode_mem: *mut c_void,
+        lenrwLS: *mut c_long,
+        leniwLS: *mut c_long,
+    ) -> c_int;
+    pub(crate) fn CVBandPrecGetNumRhsEvals(cvode_mem: *mut c_void, nfevalsBP: *mut c_long)
+        -> c_int;
+}
+
+// cvodes/cvodes_bbdpre.h
+pub(crate) type CVLocalFn = Option<
+    unsafe extern "C" fn(
+        Nlocal: sunindextype,
+        t: realtype,
+        y: N_Vector,
+        g: N_Vector,
+        user_data: *mut c_void,
+    ) -> c_int,
+>;
+pub(crate) type CVCommFn = Option<
+    unsafe extern "C" fn(
+        Nlocal: sunindextype,
+        t: realtype,
+        y: N_Vector,
+        user_data: *mut c_void,
+    ) -> c_int,
+>;
+
+extern "C" {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn CVBBDPrecInit(
+        cvode_mem: *mut c_void,
+        Nlocal: sunindextype,
+        mudq: sunindextype,
+        mldq: sunindextype,
+        mukeep: sunindextype,
+        mlkeep: sunindextype,
+        dqrely: realtype,
+        gloc: CVLocalFn,
+        cfn: CVCommFn,
+    ) -> c_int;
+    pub(crate) fn CVBBDPrecGetWorkSpace(
+        cvode_mem: *mut c_void,
+        lenrwBBDP: *mut c_long,
+        leniwBBDP: *mut c_long,
+    ) -> c_int;
+    pub(crate) fn CVBBDPrecGetNumGfnEvals(
+        cvode_mem: *mut c_void,
+        ngevalsBBDP: *mut c_long,
+    ) -> c_int;
+}
+
+// cvodes/cvodes_diag.h
+extern "C" {
+    pub(crate) fn CVDiag(cvode_mem: *mut c_void) -> c_int;
+    pub(crate) fn CVDiagB(cvode_mem: *mut c_void, which: c_int) -> c_int;
+    pub(crate) fn CVDiagGetWorkSpace(
+        cvode_mem: *mut c_void,
+        lenrwLS: *mut c_long,
+        leniwLS: *mut c_long,
+    ) -> c_int;
+    pub(crate) fn CVDiagGetNumRhsEvals(cvode_mem: *mut c_void, nfevalsLS: *mut c_long) -> c_int;
+    pub(crate) fn CVDiagGetLastFlag(cvode_mem: *mut c_void, flag: *mut c_long) -> c_int;
+}